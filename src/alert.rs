@@ -0,0 +1,672 @@
+//! Webhook notifications and PagerDuty or Opsgenie incidents when an alert
+//! starts or stops firing, for setups without Prometheus and Alertmanager
+use crate::{config, error::summary};
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+/// How long a webhook may take to accept a notification
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_FIRING_TEMPLATE: &str = "[FIRING] {alert} on {target}: {value}";
+const DEFAULT_RESOLVED_TEMPLATE: &str = "[RESOLVED] {alert} on {target}: {value}";
+
+const PAGERDUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+const OPSGENIE_ALERTS_URL: &str = "https://api.opsgenie.com/v2/alerts";
+/// Longest summary PagerDuty accepts
+const PAGERDUTY_SUMMARY_LEN: usize = 1024;
+/// Longest message Opsgenie accepts
+const OPSGENIE_MESSAGE_LEN: usize = 130;
+/// The source of the incidents
+const SOURCE: &str = "ibc-watcher";
+
+/// Every alert and its severity unless `alerts.severities` sets another one.
+/// Clients which cannot update and closed channels stop the relaying for
+/// good, a node which is not synced stops all the queries of its chain, and
+/// a backlog only needs a look.
+pub const ALERTS: &[(&str, AlertSeverity)] = &[
+    ("ibc_client_status", AlertSeverity::Critical),
+    ("ibc_client_onchain_status", AlertSeverity::Critical),
+    ("ibc_channel_state", AlertSeverity::Critical),
+    ("ibc_connection_state", AlertSeverity::Critical),
+    ("ibc_query_node_sync_status", AlertSeverity::Error),
+    ("ibc_status", AlertSeverity::Warning),
+    ("ibc_unrelayed_acks_status", AlertSeverity::Warning),
+    ("ibc_packet_age_status", AlertSeverity::Warning),
+    ("ibc_packets_near_timeout", AlertSeverity::Warning),
+    ("ibc_packets_timed_out", AlertSeverity::Warning),
+];
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+#[derive(Default)]
+struct State {
+    config: config::AlertsConfig,
+    client: reqwest::Client,
+    /// firing alerts and when they were last notified
+    firing: HashMap<AlertKey, Instant>,
+    /// delivers the notifications one after the other, so a resolved alert
+    /// never overtakes its firing one
+    outbox: Option<mpsc::UnboundedSender<Delivery>>,
+}
+
+struct Delivery {
+    request: reqwest::RequestBuilder,
+    url: String,
+    message: String,
+}
+
+impl State {
+    fn outbox(&mut self) -> &mpsc::UnboundedSender<Delivery> {
+        if self.outbox.as_ref().is_none_or(|x| x.is_closed()) {
+            let (sender, mut receiver) = mpsc::unbounded_channel::<Delivery>();
+            tokio::task::spawn(async move {
+                while let Some(delivery) = receiver.recv().await {
+                    match delivery
+                        .request
+                        .send()
+                        .await
+                        .and_then(|x| x.error_for_status())
+                    {
+                        Ok(_) => info!("sent alert to {}: {}", delivery.url, delivery.message),
+                        Err(e) => error!("cannot send alert to {}: {}", delivery.url, summary(&e)),
+                    }
+                }
+            });
+            self.outbox = Some(sender);
+        }
+        self.outbox.as_ref().unwrap()
+    }
+}
+
+/// An alert of a chain, or of a channel when port_id and channel_id are set
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Alert {
+    /// the metric the alert follows, e.g. ibc_status
+    pub alert: &'static str,
+    pub chain_id: String,
+    pub port_id: String,
+    pub channel_id: String,
+    pub destination_chain_id: String,
+    pub value: String,
+    pub threshold: String,
+    /// set by `update`, from `alerts.severities` or `ALERTS`
+    #[serde(skip)]
+    pub severity: AlertSeverity,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct AlertKey {
+    alert: &'static str,
+    chain_id: String,
+    port_id: String,
+    channel_id: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// How urgent an alert is, in the order of the PagerDuty severities
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Critical,
+    Error,
+    #[default]
+    Warning,
+}
+
+impl AlertSeverity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AlertSeverity::Critical => "critical",
+            AlertSeverity::Error => "error",
+            AlertSeverity::Warning => "warning",
+        }
+    }
+
+    fn opsgenie_priority(&self) -> &'static str {
+        match self {
+            AlertSeverity::Critical => "P1",
+            AlertSeverity::Error => "P2",
+            AlertSeverity::Warning => "P3",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    status: AlertStatus,
+    severity: AlertSeverity,
+    #[serde(flatten)]
+    alert: &'a Alert,
+    message: String,
+}
+
+impl Alert {
+    pub fn chain(alert: &'static str, chain_id: &str) -> Self {
+        Self {
+            alert,
+            chain_id: chain_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn channel(alert: &'static str, chain_id: &str, chain_channel: &config::Channel) -> Self {
+        Self {
+            alert,
+            chain_id: chain_id.to_string(),
+            port_id: chain_channel.port_id.clone(),
+            channel_id: chain_channel.channel_id.clone(),
+            destination_chain_id: chain_channel.destination_chain_id.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn value(mut self, value: String) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn threshold(mut self, threshold: String) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Stays the same while the alert fires and resolves, so the incident it
+    /// opens is also the one it closes
+    pub fn dedup_key(&self) -> String {
+        if self.channel_id.is_empty() {
+            format!("{}/{}/{}", SOURCE, self.alert, self.chain_id)
+        } else {
+            format!(
+                "{}/{}/{}/{}/{}",
+                SOURCE, self.alert, self.chain_id, self.port_id, self.channel_id
+            )
+        }
+    }
+
+    fn key(&self) -> AlertKey {
+        AlertKey {
+            alert: self.alert,
+            chain_id: self.chain_id.clone(),
+            port_id: self.port_id.clone(),
+            channel_id: self.channel_id.clone(),
+        }
+    }
+
+    /// The chain, or the channel and its destination
+    fn target(&self) -> String {
+        if self.channel_id.is_empty() {
+            self.chain_id.clone()
+        } else {
+            format!(
+                "{} {}/{} to {}",
+                self.chain_id, self.port_id, self.channel_id, self.destination_chain_id
+            )
+        }
+    }
+}
+
+/// The severity configured for the alert, or its default
+fn severity(config: &config::AlertsConfig, alert: &str) -> AlertSeverity {
+    match config.severities.get(alert) {
+        Some(severity) => *severity,
+        None => ALERTS
+            .iter()
+            .find(|(name, _)| *name == alert)
+            .map(|(_, severity)| *severity)
+            .unwrap_or_default(),
+    }
+}
+
+/// Replaces the placeholders `{status}`, `{severity}`, `{alert}`, `{target}`,
+/// `{chain_id}`, `{port_id}`, `{channel_id}`, `{destination_chain_id}`,
+/// `{value}` and `{threshold}` of the template
+pub fn render(template: &str, status: AlertStatus, alert: &Alert) -> String {
+    let status = match status {
+        AlertStatus::Firing => "firing",
+        AlertStatus::Resolved => "resolved",
+    };
+    [
+        ("{status}", status),
+        ("{severity}", alert.severity.as_str()),
+        ("{alert}", alert.alert),
+        ("{target}", &alert.target()),
+        ("{chain_id}", &alert.chain_id),
+        ("{port_id}", &alert.port_id),
+        ("{channel_id}", &alert.channel_id),
+        ("{destination_chain_id}", &alert.destination_chain_id),
+        ("{value}", &alert.value),
+        ("{threshold}", &alert.threshold),
+    ]
+    .iter()
+    .fold(template.to_string(), |message, (placeholder, value)| {
+        message.replace(placeholder, value)
+    })
+}
+
+/// Applies the `[alerts]` section, alerts which are firing stay firing
+pub fn configure(alerts: Option<&config::AlertsConfig>) {
+    let mut state = STATE.lock().unwrap();
+    state.config = alerts.cloned().unwrap_or_default();
+    state.client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .expect("http client can be built");
+}
+
+/// Notifies the webhooks when the alert starts or stops firing, and again
+/// every repeat_interval while it keeps firing
+pub fn update(mut alert: Alert, firing: bool) {
+    let mut state = STATE.lock().unwrap();
+    alert.severity = severity(&state.config, alert.alert);
+    let key = alert.key();
+    let status = match (firing, state.firing.get(&key)) {
+        (true, None) => AlertStatus::Firing,
+        (true, Some(last_sent)) => match state.config.repeat_interval {
+            Some(repeat_interval) if last_sent.elapsed() >= repeat_interval => AlertStatus::Firing,
+            _ => return,
+        },
+        (false, Some(_)) => AlertStatus::Resolved,
+        (false, None) => return,
+    };
+    match status {
+        AlertStatus::Firing => state.firing.insert(key, Instant::now()),
+        AlertStatus::Resolved => state.firing.remove(&key),
+    };
+
+    let deliveries: Vec<Delivery> = state
+        .config
+        .webhooks
+        .iter()
+        .filter_map(|webhook| delivery(&state.client, webhook, status, &alert))
+        .collect();
+    let outbox = state.outbox();
+    for delivery in deliveries {
+        // the worker only stops with the runtime
+        let _ = outbox.send(delivery);
+    }
+}
+
+/// Drops the alerts of a chain which is no longer tracked, without
+/// notifying
+pub fn forget_chain(chain_id: &str) {
+    STATE
+        .lock()
+        .unwrap()
+        .firing
+        .retain(|key, _| key.chain_id != chain_id);
+}
+
+/// Drops the alerts of a channel which is no longer tracked, without
+/// notifying
+pub fn forget_channel(chain_id: &str, port_id: &str, channel_id: &str) {
+    STATE.lock().unwrap().firing.retain(|key, _| {
+        key.chain_id != chain_id || key.port_id != port_id || key.channel_id != channel_id
+    });
+}
+
+fn delivery(
+    client: &reqwest::Client,
+    webhook: &config::Webhook,
+    status: AlertStatus,
+    alert: &Alert,
+) -> Option<Delivery> {
+    let template = match status {
+        AlertStatus::Firing => webhook
+            .firing_template
+            .as_deref()
+            .unwrap_or(DEFAULT_FIRING_TEMPLATE),
+        AlertStatus::Resolved => webhook
+            .resolved_template
+            .as_deref()
+            .unwrap_or(DEFAULT_RESOLVED_TEMPLATE),
+    };
+    let message = render(template, status, alert);
+    let url = match (&webhook.url, webhook.kind) {
+        (Some(url), _) => url.to_string(),
+        (None, config::WebhookKind::Pagerduty) => PAGERDUTY_EVENTS_URL.to_string(),
+        (None, config::WebhookKind::Opsgenie) => OPSGENIE_ALERTS_URL.to_string(),
+        // rejected by config::validate
+        (None, _) => return None,
+    };
+    let request = match webhook.kind {
+        config::WebhookKind::Slack => client.post(&url).json(&json!({ "text": message })),
+        config::WebhookKind::Discord => client.post(&url).json(&json!({ "content": message })),
+        config::WebhookKind::Generic => client.post(&url).json(&Notification {
+            status,
+            severity: alert.severity,
+            alert,
+            message: message.clone(),
+        }),
+        config::WebhookKind::Pagerduty => client
+            .post(&url)
+            .json(&pagerduty_event(webhook, status, alert, &message)),
+        config::WebhookKind::Opsgenie => {
+            let request = match status {
+                AlertStatus::Firing => client.post(&url).json(&opsgenie_alert(alert, &message)),
+                AlertStatus::Resolved => client
+                    .post(opsgenie_close_url(&url, &alert.dedup_key())?)
+                    .json(&json!({ "source": SOURCE, "note": message })),
+            };
+            request.header(
+                reqwest::header::AUTHORIZATION,
+                format!(
+                    "GenieKey {}",
+                    webhook.api_key.as_deref().unwrap_or_default()
+                ),
+            )
+        }
+    };
+    Some(Delivery {
+        request,
+        url,
+        message,
+    })
+}
+
+/// An Events API v2 event, triggering or resolving the incident of the alert
+fn pagerduty_event(
+    webhook: &config::Webhook,
+    status: AlertStatus,
+    alert: &Alert,
+    message: &str,
+) -> serde_json::Value {
+    let routing_key = webhook.routing_key.as_deref().unwrap_or_default();
+    match status {
+        AlertStatus::Firing => json!({
+            "routing_key": routing_key,
+            "event_action": "trigger",
+            "dedup_key": alert.dedup_key(),
+            "payload": {
+                "summary": truncate(message, PAGERDUTY_SUMMARY_LEN),
+                "source": alert.chain_id,
+                "severity": alert.severity,
+                "component": alert.target(),
+                "group": alert.alert,
+                "class": SOURCE,
+                "custom_details": alert,
+            },
+        }),
+        AlertStatus::Resolved => json!({
+            "routing_key": routing_key,
+            "event_action": "resolve",
+            "dedup_key": alert.dedup_key(),
+        }),
+    }
+}
+
+/// An Opsgenie alert, its alias is the dedup key so it can be closed
+fn opsgenie_alert(alert: &Alert, message: &str) -> serde_json::Value {
+    json!({
+        "message": truncate(message, OPSGENIE_MESSAGE_LEN),
+        "alias": alert.dedup_key(),
+        "description": message,
+        "priority": alert.severity.opsgenie_priority(),
+        "source": SOURCE,
+        "tags": [alert.alert, alert.chain_id],
+        "details": alert,
+    })
+}
+
+/// `{url}/{alias}/close?identifierType=alias`, with the alias escaped
+fn opsgenie_close_url(url: &str, alias: &str) -> Option<reqwest::Url> {
+    let mut url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(e) => {
+            error!("cannot close alert {} on {}: {}", alias, url, e);
+            return None;
+        }
+    };
+    url.path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .push(alias)
+        .push("close");
+    url.query_pairs_mut().append_pair("identifierType", "alias");
+    Some(url)
+}
+
+fn truncate(message: &str, len: usize) -> String {
+    message.chars().take(len).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+    use warp::Filter;
+
+    lazy_static! {
+        /// The tests share STATE, one configures it at a time
+        static ref SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+    }
+
+    #[derive(Debug)]
+    struct Received {
+        path: String,
+        query: HashMap<String, String>,
+        authorization: Option<String>,
+        body: serde_json::Value,
+    }
+
+    /// Takes the shared state and starts a delivery worker on the runtime of
+    /// the test
+    async fn serial() -> tokio::sync::MutexGuard<'static, ()> {
+        let guard = SERIAL.lock().await;
+        STATE.lock().unwrap().outbox = None;
+        guard
+    }
+
+    /// A webhook receiver on a free local port, forwarding every request it
+    /// gets
+    fn webhook_stand_in() -> (tendermint_rpc::Url, mpsc::UnboundedReceiver<Received>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let route = warp::post()
+            .and(warp::path::full())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(warp::body::json())
+            .map(
+                move |path: warp::path::FullPath, query, authorization, body: serde_json::Value| {
+                    sender
+                        .send(Received {
+                            path: path.as_str().to_string(),
+                            query,
+                            authorization,
+                            body,
+                        })
+                        .unwrap();
+                    warp::reply()
+                },
+            );
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(server);
+        (format!("http://{}/hook", addr).parse().unwrap(), receiver)
+    }
+
+    async fn received(receiver: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("webhook was called")
+            .unwrap()
+    }
+
+    fn webhook(kind: config::WebhookKind, url: tendermint_rpc::Url) -> config::Webhook {
+        config::Webhook {
+            kind,
+            url: Some(url),
+            routing_key: None,
+            api_key: None,
+            firing_template: None,
+            resolved_template: None,
+        }
+    }
+
+    #[test]
+    fn render_placeholders() {
+        let alert =
+            Alert::chain("ibc_query_node_sync_status", "chain_A").value("height 10".to_string());
+        assert_eq!(
+            render(DEFAULT_FIRING_TEMPLATE, AlertStatus::Firing, &alert),
+            "[FIRING] ibc_query_node_sync_status on chain_A: height 10"
+        );
+        let alert = Alert {
+            alert: "ibc_status",
+            chain_id: "chain_A".to_string(),
+            port_id: "transfer".to_string(),
+            channel_id: "channel-0".to_string(),
+            destination_chain_id: "chain_B".to_string(),
+            value: "25".to_string(),
+            threshold: "10".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            render(
+                "{status}: {target} {value}/{threshold}",
+                AlertStatus::Resolved,
+                &alert
+            ),
+            "resolved: chain_A transfer/channel-0 to chain_B 25/10"
+        );
+    }
+
+    #[test]
+    fn configured_severity_replaces_the_default() {
+        let mut config = config::AlertsConfig::default();
+        assert_eq!(
+            severity(&config, "ibc_channel_state"),
+            AlertSeverity::Critical
+        );
+        assert_eq!(
+            severity(&config, "ibc_unrelayed_acks_status"),
+            AlertSeverity::Warning
+        );
+        config.severities.insert(
+            "ibc_unrelayed_acks_status".to_string(),
+            AlertSeverity::Error,
+        );
+        assert_eq!(
+            severity(&config, "ibc_unrelayed_acks_status"),
+            AlertSeverity::Error
+        );
+    }
+
+    #[tokio::test]
+    async fn notify_on_transitions_only() {
+        let _serial = serial().await;
+        let (generic_url, mut generic) = webhook_stand_in();
+        let (slack_url, mut slack) = webhook_stand_in();
+        configure(Some(&config::AlertsConfig {
+            repeat_interval: None,
+            severities: Default::default(),
+            webhooks: vec![
+                webhook(config::WebhookKind::Generic, generic_url),
+                config::Webhook {
+                    firing_template: Some("{alert} {chain_id} {value}".to_string()),
+                    ..webhook(config::WebhookKind::Slack, slack_url)
+                },
+            ],
+        }));
+
+        let alert = || Alert::chain("ibc_query_node_sync_status", "alert-1");
+        update(alert().value("height 10".to_string()), false);
+        update(alert().value("height 10".to_string()), true);
+        // still firing, deduplicated
+        update(alert().value("height 10".to_string()), true);
+        update(alert().value("height 11".to_string()), false);
+
+        let body = received(&mut generic).await.body;
+        assert_eq!(body["status"], "firing");
+        assert_eq!(body["severity"], "error");
+        assert_eq!(body["chain_id"], "alert-1");
+        assert_eq!(body["value"], "height 10");
+        assert_eq!(
+            received(&mut slack).await.body,
+            json!({ "text": "ibc_query_node_sync_status alert-1 height 10" })
+        );
+
+        let body = received(&mut generic).await.body;
+        assert_eq!(body["status"], "resolved");
+        assert_eq!(
+            body["message"],
+            "[RESOLVED] ibc_query_node_sync_status on alert-1: height 11"
+        );
+        received(&mut slack).await;
+        assert!(generic.try_recv().is_err());
+        configure(None);
+    }
+
+    #[tokio::test]
+    async fn open_and_close_incidents() {
+        let _serial = serial().await;
+        let (pagerduty_url, mut pagerduty) = webhook_stand_in();
+        let (opsgenie_url, mut opsgenie) = webhook_stand_in();
+        configure(Some(&config::AlertsConfig {
+            repeat_interval: None,
+            severities: Default::default(),
+            webhooks: vec![
+                config::Webhook {
+                    routing_key: Some("routing-key".to_string()),
+                    ..webhook(config::WebhookKind::Pagerduty, pagerduty_url)
+                },
+                config::Webhook {
+                    api_key: Some("api-key".to_string()),
+                    ..webhook(config::WebhookKind::Opsgenie, opsgenie_url)
+                },
+            ],
+        }));
+
+        let alert = Alert {
+            alert: "ibc_client_status",
+            chain_id: "incident-1".to_string(),
+            port_id: "transfer".to_string(),
+            channel_id: "channel-0".to_string(),
+            destination_chain_id: "incident-2".to_string(),
+            value: "3600s before client expiration".to_string(),
+            threshold: "1d".to_string(),
+            ..Default::default()
+        };
+        update(alert.clone(), true);
+        update(alert.clone(), false);
+
+        let dedup_key = "ibc-watcher/ibc_client_status/incident-1/transfer/channel-0";
+        let trigger = received(&mut pagerduty).await.body;
+        assert_eq!(trigger["routing_key"], "routing-key");
+        assert_eq!(trigger["event_action"], "trigger");
+        assert_eq!(trigger["dedup_key"], dedup_key);
+        assert_eq!(trigger["payload"]["severity"], "critical");
+        assert_eq!(trigger["payload"]["source"], "incident-1");
+        let resolve = received(&mut pagerduty).await.body;
+        assert_eq!(resolve["event_action"], "resolve");
+        assert_eq!(resolve["dedup_key"], dedup_key);
+
+        let create = received(&mut opsgenie).await;
+        assert_eq!(create.path, "/hook");
+        assert_eq!(create.authorization.as_deref(), Some("GenieKey api-key"));
+        assert_eq!(create.body["alias"], dedup_key);
+        assert_eq!(create.body["priority"], "P1");
+        let close = received(&mut opsgenie).await;
+        assert_eq!(
+            close.path,
+            "/hook/ibc-watcher%2Fibc_client_status%2Fincident-1%2Ftransfer%2Fchannel-0/close"
+        );
+        assert_eq!(close.query["identifierType"], "alias");
+        assert_eq!(close.authorization.as_deref(), Some("GenieKey api-key"));
+        configure(None);
+    }
+}
@@ -1,16 +1,21 @@
 use cosmos_ibc_watcher::{
-    config,
+    check, config, dashboard,
     handle::ibc_status_collector,
-    telemetry::{metrics_handler, register_custom_metrics},
+    push, rules,
+    status::{liveness_handler, readiness_handler, status_handler},
+    telemetry::{self, metrics_handler},
     DEFAULT_CONFIG_PATH,
 };
 use env_logger::Builder;
 use log::{error, info, LevelFilter};
 use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::result::Result;
 use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
 use warp::Filter;
 
 /// Helper sub-commands
@@ -25,6 +30,67 @@ enum IbcWatcher {
         #[structopt(short)]
         config_path: Option<PathBuf>,
     },
+    #[structopt(
+        name = "check",
+        about = "run all checks once, print a report and exit non-zero if any threshold is breached"
+    )]
+    Check {
+        #[structopt(short)]
+        config_path: Option<PathBuf>,
+        /// print the report as JSON instead of a table
+        #[structopt(long)]
+        json: bool,
+        /// report a node as not synced when its latest block is older, e.g.
+        /// 2m, default is the node sync refresh of 60s
+        #[structopt(long, parse(try_from_str = duration_str::parse))]
+        max_block_age: Option<Duration>,
+    },
+    #[structopt(
+        name = "validate-config",
+        about = "report all problems of the config file and exit non-zero if it cannot be used"
+    )]
+    ValidateConfig {
+        #[structopt(short)]
+        config_path: Option<PathBuf>,
+        /// also check that every gRPC endpoint answers
+        #[structopt(long)]
+        probe: bool,
+    },
+    #[structopt(
+        name = "gen-rules",
+        about = "print Prometheus alerting rules for the metrics of the configured chains"
+    )]
+    GenRules {
+        #[structopt(short)]
+        config_path: Option<PathBuf>,
+        /// rules for a rule_files entry, or prometheus-rule for the Prometheus Operator
+        #[structopt(long, default_value = "rules")]
+        format: rules::Format,
+        /// link every alert to <runbook-url>/<alert name in lowercase>
+        #[structopt(long)]
+        runbook_url: Option<String>,
+        /// name of the PrometheusRule resource
+        #[structopt(long)]
+        name: Option<String>,
+        /// namespace of the PrometheusRule resource
+        #[structopt(long)]
+        namespace: Option<String>,
+        /// write the rules to this file instead of stdout
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "gen-dashboard",
+        about = "print the Grafana dashboard for the metrics of this version"
+    )]
+    GenDashboard {
+        /// offer the chains of this config instead of the ones found in Prometheus
+        #[structopt(short)]
+        config_path: Option<PathBuf>,
+        /// write the dashboard to this file instead of stdout
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[tokio::main]
@@ -35,6 +101,35 @@ async fn main() {
     let opt = IbcWatcher::from_args();
     let result = match opt {
         IbcWatcher::Start { config_path } => start(config_path).await,
+        IbcWatcher::Check {
+            config_path,
+            json,
+            max_block_age,
+        } => run_check(config_path, json, max_block_age).await,
+        IbcWatcher::ValidateConfig { config_path, probe } => {
+            validate_config(config_path, probe).await
+        }
+        IbcWatcher::GenRules {
+            config_path,
+            format,
+            runbook_url,
+            name,
+            namespace,
+            output,
+        } => gen_rules(
+            config_path,
+            format,
+            rules::Options {
+                runbook_url,
+                name,
+                namespace,
+            },
+            output,
+        ),
+        IbcWatcher::GenDashboard {
+            config_path,
+            output,
+        } => gen_dashboard(config_path, output),
     };
     if let Err(e) = result {
         error!("{}", e);
@@ -42,33 +137,189 @@ async fn main() {
     }
 }
 
-async fn start(config_path: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+/// The given config path, or chains.toml next to the executable
+fn resolve_config_path(
+    config_path: Option<PathBuf>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
     let default_path = format!(
         "{}/{}",
         std::env::current_exe()?.parent().unwrap().to_str().unwrap(),
         DEFAULT_CONFIG_PATH
     );
-    let cp = config_path.unwrap_or_else(|| default_path.into());
+    Ok(config_path.unwrap_or_else(|| default_path.into()))
+}
+
+async fn run_check(
+    config_path: Option<PathBuf>,
+    json: bool,
+    max_block_age: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cp = resolve_config_path(config_path)?;
+    if !cp.exists() {
+        return Err("missing chains.toml file".into());
+    }
+    let config = config::load(&cp)?;
+    let report = check::run(&config, max_block_age).await;
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        print!("{}", report);
+    }
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn validate_config(
+    config_path: Option<PathBuf>,
+    probe: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cp = resolve_config_path(config_path)?;
+    let config = config::read(&cp)?;
+    let mut issues = config::validate(&config);
+    if probe {
+        issues.extend(check::probe(&config).await);
+    }
+    if print_issues(&cp, &issues) > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Prints every issue of the config file and a summary, returns the no. of
+/// errors
+fn print_issues(config_path: &Path, issues: &[config::Issue]) -> usize {
+    for issue in issues.iter() {
+        println!("{}", issue);
+    }
+    let errors = issues
+        .iter()
+        .filter(|x| x.severity == config::Severity::Error)
+        .count();
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        config_path.display(),
+        errors,
+        issues.len() - errors
+    );
+    errors
+}
+
+fn gen_rules(
+    config_path: Option<PathBuf>,
+    format: rules::Format,
+    options: rules::Options,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cp = resolve_config_path(config_path)?;
+    let config = config::load(&cp)?;
+    let yaml = rules::to_yaml(&rules::generate(&config, &options), format, &options);
+    match output {
+        Some(output) => std::fs::write(output, yaml)?,
+        None => print!("{}", yaml),
+    }
+    Ok(())
+}
+
+fn gen_dashboard(
+    config_path: Option<PathBuf>,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config_path.map(config::load).transpose()?;
+    let json = dashboard::to_json(&dashboard::generate(config.as_ref()));
+    match output {
+        Some(output) => std::fs::write(output, json + "\n")?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+async fn start(config_path: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let cp = resolve_config_path(config_path)?;
     info!("config file: {}", cp.display());
     if !cp.exists() {
         Err("missing chains.toml file".into())
     } else {
-        let config = config::load(cp).expect("could not parse config");
+        let config = config::read(&cp)?;
+        let issues = config::validate(&config);
+        if issues.iter().any(|x| x.severity == config::Severity::Error) {
+            print_issues(&cp, &issues);
+            std::process::exit(1);
+        }
+        let reloads = config::watch(cp);
 
-        register_custom_metrics();
+        telemetry::init(&config.prometheus);
+        #[cfg(feature = "otel")]
+        let otel = config
+            .otel
+            .as_ref()
+            .map(cosmos_ibc_watcher::otel::init)
+            .transpose()?;
+        #[cfg(not(feature = "otel"))]
+        if config.otel.is_some() {
+            error!("otel is ignored, ibc-watcher is built without the otel feature");
+        }
         let metrics_route = warp::path!("metrics").and_then(metrics_handler);
-        tokio::task::spawn(ibc_status_collector(config.clone()));
-
-        info!(
-            "Started prometheus metrics server: http://{}:{}/metrics",
-            &config.prometheus.host, &config.prometheus.port
-        );
-        warp::serve(metrics_route)
-            .run((
+        let status_route = warp::path!("api" / "v1" / "status").and_then(status_handler);
+        let liveness_route = warp::path!("healthz").and_then(liveness_handler);
+        let readiness_staleness = config.prometheus.readiness_staleness;
+        let readiness_route =
+            warp::path!("readyz").and_then(move || readiness_handler(readiness_staleness));
+        let routes = metrics_route
+            .or(status_route)
+            .or(liveness_route)
+            .or(readiness_route);
+        let (stop, stopped) = watch::channel(false);
+        let collector = tokio::task::spawn(ibc_status_collector(
+            config.clone(),
+            reloads,
+            wait_for_stop(stopped.clone()),
+        ));
+
+        let pusher = config
+            .prometheus
+            .push
+            .clone()
+            .map(|x| tokio::task::spawn(push::run(x, wait_for_stop(stopped.clone()))));
+
+        let (addr, server) = warp::serve(routes).try_bind_with_graceful_shutdown(
+            (
                 Ipv4Addr::from_str(&config.prometheus.host)?,
                 config.prometheus.port as u16,
-            ))
-            .await;
+            ),
+            wait_for_stop(stopped),
+        )?;
+        info!("Started prometheus metrics server: http://{}/metrics", addr);
+        let server = tokio::task::spawn(server);
+
+        shutdown_signal().await?;
+        info!("shutting down");
+        stop.send_replace(true);
+        server.await?;
+        collector.await?;
+        if let Some(pusher) = pusher {
+            pusher.await?;
+        }
+        #[cfg(feature = "otel")]
+        if let Some(otel) = otel {
+            otel.shutdown();
+        }
         Ok(())
     }
 }
+
+/// Completes on SIGTERM or ctrl-c
+async fn shutdown_signal() -> std::io::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        res = tokio::signal::ctrl_c() => res?,
+    }
+    Ok(())
+}
+
+async fn wait_for_stop(mut stopped: watch::Receiver<bool>) {
+    // an error means the sender is gone, which only happens on exit
+    let _ = stopped.wait_for(|stopped| *stopped).await;
+}
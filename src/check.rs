@@ -0,0 +1,426 @@
+//! The checks `ibc-watcher check` runs once against the configured chains and
+//! channels, and the endpoint probe of `validate-config --probe`
+use crate::{config, error::summary, query};
+use duration_str::parse;
+use serde::Serialize;
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinSet;
+
+/// How long `probe` waits for an endpoint to answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The outcome of a single check
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Breached,
+    Error,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "ok"),
+            CheckStatus::Breached => write!(f, "breached"),
+            CheckStatus::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// One row of the report, port_id, channel_id and destination_chain_id are
+/// empty for the checks of a chain
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckResult {
+    pub chain_id: String,
+    pub port_id: String,
+    pub channel_id: String,
+    pub destination_chain_id: String,
+    pub check: &'static str,
+    pub value: String,
+    pub threshold: String,
+    pub status: CheckStatus,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Report {
+    pub results: Vec<CheckResult>,
+}
+
+impl Report {
+    /// true when no check was breached or failed to run
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|x| x.status == CheckStatus::Ok)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report can be serialized")
+    }
+}
+
+impl fmt::Display for Report {
+    /// Renders the report as a table with a column per field
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = [
+            "CHAIN",
+            "PORT",
+            "CHANNEL",
+            "DESTINATION",
+            "CHECK",
+            "VALUE",
+            "THRESHOLD",
+            "STATUS",
+        ];
+        let rows: Vec<[String; 8]> = self
+            .results
+            .iter()
+            .map(|x| {
+                [
+                    x.chain_id.clone(),
+                    x.port_id.clone(),
+                    x.channel_id.clone(),
+                    x.destination_chain_id.clone(),
+                    x.check.to_string(),
+                    x.value.clone(),
+                    x.threshold.clone(),
+                    x.status.to_string(),
+                ]
+            })
+            .collect();
+        let mut widths = header.map(str::len);
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let header = header.map(str::to_string);
+        for row in std::iter::once(&header).chain(rows.iter()) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            writeln!(f, "{}", line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the node sync check of every chain and the commitment and client
+/// expiry checks of every configured channel once.
+///
+/// A node counts as synced when its latest block is at most `max_block_age`
+/// old, NODE_SYNC_REFRESH by default. The node sync tracker of the watcher
+/// instead compares the heights of two polls NODE_SYNC_REFRESH apart, which
+/// a single check cannot do without waiting that long. A node whose block
+/// times are off, e.g. by a skewed clock of the validators, or a chain whose
+/// blocks are slower than the threshold can pass one and fail the other.
+pub async fn run(config: &config::Config, max_block_age: Option<Duration>) -> Report {
+    let max_block_age = max_block_age.unwrap_or(config::NODE_SYNC_REFRESH);
+    let mut chains = JoinSet::new();
+    for (index, chain_config) in config.chains.iter().cloned().enumerate() {
+        chains.spawn(async move { (index, check_chain(chain_config, max_block_age).await) });
+    }
+    let mut results = Vec::with_capacity(config.chains.len());
+    while let Some(res) = chains.join_next().await {
+        results.push(res.expect("check does not panic"));
+    }
+    // keep the order of the config
+    results.sort_by_key(|(index, _)| *index);
+    Report {
+        results: results.into_iter().flat_map(|(_, x)| x).collect(),
+    }
+}
+
+async fn check_chain(
+    chain_config: config::ChainConfig,
+    max_block_age: Duration,
+) -> Vec<CheckResult> {
+    let chain_id = &chain_config.id;
+    let client = match query::ChainClient::from_config(&chain_config) {
+        Ok(client) => client,
+        Err(e) => {
+            let mut result = chain_result(chain_id, "node_sync", "", CheckStatus::Error);
+            result.value = summary(&e);
+            return vec![result];
+        }
+    };
+
+    let threshold = format!("{}s", max_block_age.as_secs());
+    let mut results = vec![match client.get_latest_height_and_time().await.result {
+        Ok((height, time)) => {
+            let age = block_age(time, now());
+            let status = if age > max_block_age {
+                CheckStatus::Breached
+            } else {
+                CheckStatus::Ok
+            };
+            let mut result = chain_result(chain_id, "node_sync", &threshold, status);
+            result.value = format!("height {} {}s ago", height, age.as_secs());
+            result
+        }
+        Err(e) => {
+            let mut result = chain_result(chain_id, "node_sync", &threshold, CheckStatus::Error);
+            result.value = summary(&e);
+            result
+        }
+    }];
+
+    for chain_channel in chain_config.channels.iter() {
+        results.push(check_commitments(&client, chain_id, chain_channel).await);
+        results.push(check_client_expiry(&client, chain_id, chain_channel).await);
+    }
+    results
+}
+
+async fn check_commitments(
+    client: &query::ChainClient,
+    chain_id: &str,
+    chain_channel: &config::Channel,
+) -> CheckResult {
+    let mut result = channel_result(chain_id, chain_channel, "commitments");
+    result.threshold = chain_channel.min_total.clone();
+    let min_total = match chain_channel.min_total.parse::<config::Count>() {
+        Ok(min_total) => min_total,
+        Err(e) => {
+            result.status = CheckStatus::Error;
+            result.value = e;
+            return result;
+        }
+    };
+    match client
+        .get_packet_commitments_total(
+            chain_channel.port_id.clone(),
+            chain_channel.channel_id.clone(),
+        )
+        .await
+        .result
+    {
+        Ok(total) => {
+            result.value = total.to_string();
+            if total >= min_total.get() {
+                result.status = CheckStatus::Breached;
+            }
+        }
+        Err(e) => {
+            result.status = CheckStatus::Error;
+            result.value = summary(&e);
+        }
+    }
+    result
+}
+
+async fn check_client_expiry(
+    client: &query::ChainClient,
+    chain_id: &str,
+    chain_channel: &config::Channel,
+) -> CheckResult {
+    let mut result = channel_result(chain_id, chain_channel, "client_expiry");
+    let port_id = &chain_channel.port_id;
+    let channel_id = &chain_channel.channel_id;
+
+    let client_state = match client
+        .get_channel_client_state(port_id.clone(), channel_id.clone())
+        .await
+        .result
+    {
+        Ok(client_state) => client_state.client_state,
+        Err(e) => {
+            result.status = CheckStatus::Error;
+            result.value = summary(&e);
+            return result;
+        }
+    };
+    let trusting_period = client_state.trusting_period();
+    let min_time_before_client_expiration = match &chain_channel.min_time_before_client_expiration {
+        Some(x) => match parse(x) {
+            Ok(d) => d,
+            Err(e) => {
+                result.status = CheckStatus::Error;
+                result.value = summary(&e);
+                return result;
+            }
+        },
+        None => trusting_period / 3,
+    };
+    result.threshold = format!("{}s", min_time_before_client_expiration.as_secs());
+
+    match client
+        .get_latest_channel_client_consensus_state_duration(
+            port_id.clone(),
+            channel_id.clone(),
+            client_state.latest_height(),
+        )
+        .await
+        .result
+    {
+        Ok(consensus_state_time) => {
+            let time_before_expire =
+                time_before_expire(consensus_state_time, trusting_period, now());
+            result.value = format!("{}s", time_before_expire.as_secs());
+            if time_before_expire <= min_time_before_client_expiration {
+                result.status = CheckStatus::Breached;
+            }
+        }
+        Err(e) => {
+            result.status = CheckStatus::Error;
+            result.value = summary(&e);
+        }
+    }
+    result
+}
+
+/// Connects to every gRPC endpoint of the config and reports the ones which
+/// cannot serve a latest block query within PROBE_TIMEOUT
+pub async fn probe(config: &config::Config) -> Vec<config::Issue> {
+    let mut probes = JoinSet::new();
+    for (i, chain_config) in config.chains.iter().enumerate() {
+        let fallbacks = (0..chain_config.fallback_grpc_addrs.len()).map(|j| {
+            format!(
+                "chains[{}] ({}).fallback_grpc_addrs[{}]",
+                i, chain_config.id, j
+            )
+        });
+        let locations = std::iter::once(format!("chains[{}] ({}).grpc_addr", i, chain_config.id))
+            .chain(fallbacks);
+        for (location, grpc_addr) in locations.zip(chain_config.grpc_addrs()) {
+            let index = probes.len();
+            let chain_id = chain_config.id.clone();
+            let (connect_timeout, timeout) = (chain_config.connect_timeout, chain_config.timeout);
+            probes.spawn(async move {
+                let reachable = match query::ChainClient::with_timeouts(
+                    &chain_id,
+                    std::slice::from_ref(&grpc_addr),
+                    connect_timeout,
+                    timeout,
+                ) {
+                    Ok(client) => {
+                        tokio::time::timeout(PROBE_TIMEOUT, async {
+                            client.get_latest_height().await.result
+                        })
+                        .await
+                    }
+                    Err(e) => Ok(Err(e)),
+                };
+                let message = match reachable {
+                    Ok(Ok(_)) => return (index, None),
+                    Ok(Err(e)) => summary(&e),
+                    Err(_) => format!("no response within {:?}", PROBE_TIMEOUT),
+                };
+                let issue = config::Issue {
+                    severity: config::Severity::Error,
+                    location,
+                    message: format!("{} is not reachable: {}", grpc_addr, message),
+                };
+                (index, Some(issue))
+            });
+        }
+    }
+
+    let mut issues = Vec::new();
+    while let Some(res) = probes.join_next().await {
+        if let (index, Some(issue)) = res.expect("probe does not panic") {
+            issues.push((index, issue));
+        }
+    }
+    // keep the order of the config
+    issues.sort_by_key(|(index, _)| *index);
+    issues.into_iter().map(|(_, issue)| issue).collect()
+}
+
+fn chain_result(
+    chain_id: &str,
+    check: &'static str,
+    threshold: &str,
+    status: CheckStatus,
+) -> CheckResult {
+    CheckResult {
+        chain_id: chain_id.to_string(),
+        port_id: String::new(),
+        channel_id: String::new(),
+        destination_chain_id: String::new(),
+        check,
+        value: String::new(),
+        threshold: threshold.to_string(),
+        status,
+    }
+}
+
+fn channel_result(
+    chain_id: &str,
+    chain_channel: &config::Channel,
+    check: &'static str,
+) -> CheckResult {
+    CheckResult {
+        chain_id: chain_id.to_string(),
+        port_id: chain_channel.port_id.clone(),
+        channel_id: chain_channel.channel_id.clone(),
+        destination_chain_id: chain_channel.destination_chain_id.clone(),
+        check,
+        value: String::new(),
+        threshold: String::new(),
+        status: CheckStatus::Ok,
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+}
+
+fn block_age(block_time: Duration, now: Duration) -> Duration {
+    now.saturating_sub(block_time)
+}
+
+/// Time left until the client expires, zero once it has expired
+fn time_before_expire(
+    consensus_state_time: Duration,
+    trusting_period: Duration,
+    now: Duration,
+) -> Duration {
+    (consensus_state_time + trusting_period).saturating_sub(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_expiry_and_block_age() {
+        let now = Duration::from_secs(1_000);
+        let period = Duration::from_secs(300);
+        assert_eq!(
+            time_before_expire(Duration::from_secs(900), period, now),
+            Duration::from_secs(200)
+        );
+        assert_eq!(
+            time_before_expire(Duration::from_secs(500), period, now),
+            Duration::ZERO
+        );
+        assert_eq!(
+            block_age(Duration::from_secs(990), now),
+            Duration::from_secs(10)
+        );
+        assert_eq!(block_age(Duration::from_secs(1_010), now), Duration::ZERO);
+    }
+
+    #[test]
+    fn report_fails_on_breach_or_error() {
+        let mut result = chain_result("test-1", "node_sync", "60s", CheckStatus::Ok);
+        let mut report = Report {
+            results: vec![result.clone()],
+        };
+        assert!(report.passed());
+        assert_eq!(
+            report.to_string(),
+            "CHAIN   PORT  CHANNEL  DESTINATION  CHECK      VALUE  THRESHOLD  STATUS\n\
+             test-1                              node_sync         60s        ok\n"
+        );
+
+        result.status = CheckStatus::Error;
+        report.results.push(result);
+        assert!(!report.passed());
+    }
+}
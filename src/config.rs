@@ -1,10 +1,23 @@
 //! Chain configuration
-use std::collections::HashMap;
-use std::{fs, fs::File, io::Write, path::Path, time::Duration};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::{
+    fmt, fs,
+    fs::File,
+    io::Write,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
+use log::{error, info};
 use serde_derive::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
 
+use crate::alert::{AlertSeverity, ALERTS};
 use crate::error::Error;
+use crate::telemetry;
 
 pub mod default {
     use super::*;
@@ -12,15 +25,71 @@ pub mod default {
     pub fn refresh() -> Duration {
         Duration::from_secs(120)
     }
+
+    pub fn discovery_interval() -> Duration {
+        Duration::from_secs(600)
+    }
+
+    pub fn connect_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    pub fn timeout() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    pub fn push_interval() -> Duration {
+        Duration::from_secs(30)
+    }
+
+    pub fn push_job() -> String {
+        "ibc-watcher".to_string()
+    }
+
+    /// The host name, as set for containers and most shells, else the one of
+    /// the kernel
+    pub fn push_instance() -> String {
+        std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| "ibc-watcher".to_string())
+    }
+
+    pub fn otel_endpoint() -> tendermint_rpc::Url {
+        "http://127.0.0.1:4317".parse().unwrap()
+    }
+
+    pub fn otel_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub fn otel_service_name() -> String {
+        "ibc-watcher".to_string()
+    }
+
+    pub fn enabled() -> bool {
+        true
+    }
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// How often the config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+/// How often the block height of every chain is checked
+pub const NODE_SYNC_REFRESH: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default)]
     pub prometheus: PrometheusConfig,
     #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
     pub chains: Vec<ChainConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alerts: Option<AlertsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otel: Option<OtelConfig>,
 }
 
 impl Config {
@@ -29,14 +98,44 @@ impl Config {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct PrometheusConfig {
     pub host: String,
     pub port: i32,
+    /// deprecated alias of `series_ttl`
     #[serde(default)]
     #[serde(with = "humantime_serde")]
     pub reset: Option<Duration>,
+    /// series which are not updated within this duration are removed, they
+    /// are kept until their channel or chain is removed when unset
+    #[serde(default, with = "humantime_serde")]
+    pub series_ttl: Option<Duration>,
+    /// /readyz fails for a chain without a successful query within this
+    /// duration, one successful query since startup is enough when unset
+    #[serde(default, with = "humantime_serde")]
+    pub readiness_staleness: Option<Duration>,
+    /// prefix of every metric name, e.g. `mantra` for `mantra_ibc_status`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// labels added to every series, e.g. environment, region or instance
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub const_labels: BTreeMap<String, String>,
+    /// keep min_total and min_time_before_client_expiration as labels of
+    /// ibc_status, ibc_count and the client gauges, the layout before the
+    /// threshold gauges, while dashboards and alerts are migrated
+    #[serde(default)]
+    pub threshold_labels: bool,
+    /// pushes the metrics for networks Prometheus cannot scrape into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push: Option<PushConfig>,
+}
+
+impl PrometheusConfig {
+    /// `series_ttl`, or the deprecated `reset` when it is not set
+    pub fn series_ttl(&self) -> Option<Duration> {
+        self.series_ttl.or(self.reset)
+    }
 }
 
 impl Default for PrometheusConfig {
@@ -45,20 +144,221 @@ impl Default for PrometheusConfig {
             host: "0.0.0.0".to_string(),
             port: 9090,
             reset: None,
+            series_ttl: None,
+            readiness_staleness: None,
+            namespace: None,
+            const_labels: BTreeMap::new(),
+            threshold_labels: false,
+            push: None,
         }
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PushConfig {
+    pub kind: PushKind,
+    /// base url of the Pushgateway, or the remote-write endpoint
+    pub url: tendermint_rpc::Url,
+    #[serde(default = "default::push_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// job of the Pushgateway group, and label of every remote-write series
+    #[serde(default = "default::push_job")]
+    pub job: String,
+    /// instance of the Pushgateway group, and label of every remote-write
+    /// series
+    #[serde(default = "default::push_instance")]
+    pub instance: String,
+    /// basic auth, with password
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PushKind {
+    /// replaces the group of the job on a Prometheus Pushgateway
+    Pushgateway,
+    /// sends the samples as a snappy compressed remote-write request
+    RemoteWrite,
+}
+
+/// Exports the metrics and a trace per polling cycle to an OTLP collector,
+/// needs a build with the `otel` feature
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtelConfig {
+    /// gRPC endpoint of the collector
+    #[serde(default = "default::otel_endpoint")]
+    pub endpoint: tendermint_rpc::Url,
+    /// how often the metrics are exported
+    #[serde(default = "default::otel_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    /// service.name of the resource
+    #[serde(default = "default::otel_service_name")]
+    pub service_name: String,
+    #[serde(default = "default::enabled")]
+    pub metrics: bool,
+    /// a span per polling cycle with a child span per gRPC call
+    #[serde(default = "default::enabled")]
+    pub traces: bool,
+}
+
+/// Webhooks notified when an alert of `alert::ALERTS` starts or stops firing
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// notify again while an alert keeps firing, only once when unset
+    #[serde(default, with = "humantime_serde")]
+    pub repeat_interval: Option<Duration>,
+    /// severity per alert name, replacing its default
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub severities: BTreeMap<String, AlertSeverity>,
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub kind: WebhookKind,
+    /// required by slack, discord and generic, pagerduty and opsgenie
+    /// default to their public API
+    pub url: Option<tendermint_rpc::Url>,
+    /// integration key of the PagerDuty service
+    pub routing_key: Option<String>,
+    /// API key of the Opsgenie integration
+    pub api_key: Option<String>,
+    /// message of a firing alert, see `alert::render` for the placeholders
+    pub firing_template: Option<String>,
+    /// message of a resolved alert
+    pub resolved_template: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookKind {
+    /// posts `{"text": message}`
+    Slack,
+    /// posts `{"content": message}`
+    Discord,
+    /// posts the alert as JSON along with the message
+    Generic,
+    /// triggers and resolves PagerDuty incidents through Events API v2
+    Pagerduty,
+    /// creates and closes Opsgenie alerts
+    Opsgenie,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct ChainConfig {
     pub id: String,
     pub grpc_addr: tendermint_rpc::Url,
     #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub fallback_grpc_addrs: Vec<tendermint_rpc::Url>,
+    /// how long connecting to an endpoint may take before the next one is tried
+    #[serde(default = "default::connect_timeout", with = "humantime_serde")]
+    pub connect_timeout: Duration,
+    /// how long an endpoint may take to answer a query
+    #[serde(default = "default::timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
     pub channels: Vec<Channel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<Discovery>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Tracks the open channels of a chain without listing them by hand. The
+/// thresholds apply to every discovered channel; channels listed under
+/// `channels` keep their own.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Discovery {
+    /// only channels bound to these ports, all ports when empty
+    #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    pub port_ids: Vec<String>,
+    #[serde(default = "default::discovery_interval", with = "humantime_serde")]
+    pub interval: Duration,
+    pub min_time_before_client_expiration: Option<String>,
+    pub min_total: String,
+    #[serde(default = "default::refresh", with = "humantime_serde")]
+    pub refresh: Duration,
+}
+
+impl Discovery {
+    pub fn matches_port(&self, port_id: &str) -> bool {
+        self.port_ids.is_empty() || self.port_ids.iter().any(|x| x == port_id)
+    }
+
+    /// The config of a discovered channel
+    pub fn channel(&self, port_id: &str, channel_id: &str, destination_chain_id: &str) -> Channel {
+        Channel {
+            port_id: port_id.to_string(),
+            channel_id: channel_id.to_string(),
+            destination_chain_id: destination_chain_id.to_string(),
+            min_time_before_client_expiration: self.min_time_before_client_expiration.clone(),
+            min_total: self.min_total.clone(),
+            min_unrelayed_acks: None,
+            max_packet_age: None,
+            timeout_window: None,
+            refresh: self.refresh,
+        }
+    }
+}
+
+impl Channel {
+    pub fn min_unrelayed_acks(&self) -> &str {
+        self.min_unrelayed_acks.as_ref().unwrap_or(&self.min_total)
+    }
+}
+
+/// A count threshold such as min_total. It is exported by i64 gauges, so it
+/// cannot go above i64::MAX.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Count(u64);
+
+impl Count {
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn as_i64(self) -> i64 {
+        self.0 as i64
+    }
+}
+
+impl FromStr for Count {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse::<u64>() {
+            Ok(x) if x > i64::MAX as u64 => {
+                Err(format!("{:?} is above the maximum of {}", value, i64::MAX))
+            }
+            Ok(x) => Ok(Count(x)),
+            Err(e) => Err(format!("{:?} is not a number: {}", value, e)),
+        }
+    }
+}
+
+impl fmt::Display for Count {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ChainConfig {
+    /// All gRPC endpoints of the chain, `grpc_addr` first
+    pub fn grpc_addrs(&self) -> Vec<String> {
+        std::iter::once(&self.grpc_addr)
+            .chain(self.fallback_grpc_addrs.iter())
+            .map(|url| url.to_string())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Channel {
     pub port_id: String,
@@ -66,30 +366,475 @@ pub struct Channel {
     pub destination_chain_id: String,
     pub min_time_before_client_expiration: Option<String>,
     pub min_total: String,
+    /// defaults to min_total
+    pub min_unrelayed_acks: Option<String>,
+    #[serde(default, with = "humantime_serde")]
+    pub max_packet_age: Option<Duration>,
+    /// Enables timeout tracking, which needs the destination chain configured
+    #[serde(default, with = "humantime_serde")]
+    pub timeout_window: Option<Duration>,
     #[serde(default = "default::refresh", with = "humantime_serde")]
     pub refresh: Duration,
 }
 
-/// Attempt to load and parse the TOML config file as a `Config`.
+/// Attempt to load and parse the TOML config file as a `Config`, rejecting
+/// it if `validate` finds any error.
 pub fn load(path: impl AsRef<Path>) -> Result<Config, Error> {
+    let config = read(path)?;
+    let errors: Vec<String> = validate(&config)
+        .into_iter()
+        .filter(|x| x.severity == Severity::Error)
+        .map(|x| x.to_string())
+        .collect();
+    if !errors.is_empty() {
+        return Err(Error::config_invalid(errors));
+    }
+    Ok(config)
+}
+
+/// Attempt to parse the TOML config file as a `Config` without validating it.
+pub fn read(path: impl AsRef<Path>) -> Result<Config, Error> {
     let config_toml = fs::read_to_string(&path).map_err(Error::config_io)?;
 
-    let config = toml::from_str::<Config>(&config_toml[..]).map_err(Error::config_decode)?;
-    check_parse_u64(config.clone())?;
-    Ok(config)
+    toml::from_str::<Config>(&config_toml[..]).map_err(Error::config_decode)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// the watcher cannot run with it
+    Error,
+    /// the watcher runs, but tracks less than the config suggests
+    Warning,
+}
+
+/// A problem found in the config, located by its TOML path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.location, self.message)
+    }
+}
+
+/// `[a-zA-Z_][a-zA-Z0-9_]*`, valid for label names and metric name prefixes
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
 }
 
-// Attempt to parse min_total to u64
-pub fn check_parse_u64(config: Config) -> Result<(), Error> {
-    for chain_config in config.chains.iter() {
-        for chain_channel in chain_config.channels.iter() {
-            chain_channel
-                .min_total
-                .parse::<u64>()
-                .map_err(Error::config_parse_u64)?;
+/// Checks the parsed config for the problems serde cannot catch and returns
+/// all of them at once
+pub fn validate(config: &Config) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut error = |location: String, message: String| {
+        issues.push(Issue {
+            severity: Severity::Error,
+            location,
+            message,
+        })
+    };
+
+    if config.prometheus.host.parse::<Ipv4Addr>().is_err() {
+        error(
+            "prometheus.host".to_string(),
+            format!("{:?} is not an IPv4 address", config.prometheus.host),
+        );
+    }
+    if u16::try_from(config.prometheus.port).is_err() {
+        error(
+            "prometheus.port".to_string(),
+            format!("{} is not a valid port", config.prometheus.port),
+        );
+    }
+    if config.prometheus.reset == Some(Duration::ZERO) {
+        error(
+            "prometheus.reset".to_string(),
+            "must not be zero".to_string(),
+        );
+    }
+    if config.prometheus.series_ttl == Some(Duration::ZERO) {
+        error(
+            "prometheus.series_ttl".to_string(),
+            "must not be zero".to_string(),
+        );
+    }
+    if let Some(alerts) = &config.alerts {
+        if alerts.repeat_interval == Some(Duration::ZERO) {
+            error(
+                "alerts.repeat_interval".to_string(),
+                "must not be zero".to_string(),
+            );
+        }
+        for name in alerts.severities.keys() {
+            if !ALERTS.iter().any(|(alert, _)| alert == name) {
+                error(
+                    format!("alerts.severities.{}", name),
+                    "is not an alert".to_string(),
+                );
+            }
+        }
+        for (i, webhook) in alerts.webhooks.iter().enumerate() {
+            let location = format!("alerts.webhooks[{}]", i);
+            match &webhook.url {
+                Some(url)
+                    if !matches!(
+                        url.scheme(),
+                        tendermint_rpc::Scheme::Http | tendermint_rpc::Scheme::Https
+                    ) =>
+                {
+                    error(
+                        format!("{}.url", location),
+                        format!("{} is not an http(s) url", url),
+                    );
+                }
+                Some(_) => {}
+                None => {
+                    if matches!(
+                        webhook.kind,
+                        WebhookKind::Slack | WebhookKind::Discord | WebhookKind::Generic
+                    ) {
+                        error(
+                            format!("{}.url", location),
+                            "is required by this kind".to_string(),
+                        );
+                    }
+                }
+            }
+            if webhook.kind == WebhookKind::Pagerduty && webhook.routing_key.is_none() {
+                error(
+                    format!("{}.routing_key", location),
+                    "is required by pagerduty".to_string(),
+                );
+            }
+            if webhook.kind == WebhookKind::Opsgenie && webhook.api_key.is_none() {
+                error(
+                    format!("{}.api_key", location),
+                    "is required by opsgenie".to_string(),
+                );
+            }
         }
     }
-    Ok(())
+    if config.prometheus.readiness_staleness == Some(Duration::ZERO) {
+        error(
+            "prometheus.readiness_staleness".to_string(),
+            "must not be zero".to_string(),
+        );
+    }
+    if let Some(push) = &config.prometheus.push {
+        if !matches!(
+            push.url.scheme(),
+            tendermint_rpc::Scheme::Http | tendermint_rpc::Scheme::Https
+        ) {
+            error(
+                "prometheus.push.url".to_string(),
+                format!("{} is not an http(s) url", push.url),
+            );
+        }
+        if push.interval.is_zero() {
+            error(
+                "prometheus.push.interval".to_string(),
+                "must not be zero".to_string(),
+            );
+        }
+        if push.kind == PushKind::Pushgateway && push.job.is_empty() {
+            error(
+                "prometheus.push.job".to_string(),
+                "must not be empty".to_string(),
+            );
+        }
+        if push.password.is_some() && push.username.is_none() {
+            error(
+                "prometheus.push.username".to_string(),
+                "is required with a password".to_string(),
+            );
+        }
+    }
+    if let Some(otel) = &config.otel {
+        if !matches!(
+            otel.endpoint.scheme(),
+            tendermint_rpc::Scheme::Http | tendermint_rpc::Scheme::Https
+        ) {
+            error(
+                "otel.endpoint".to_string(),
+                format!("{} is not an http(s) url", otel.endpoint),
+            );
+        }
+        if otel.interval.is_zero() {
+            error("otel.interval".to_string(), "must not be zero".to_string());
+        }
+        if otel.service_name.is_empty() {
+            error(
+                "otel.service_name".to_string(),
+                "must not be empty".to_string(),
+            );
+        }
+    }
+    if let Some(namespace) = &config.prometheus.namespace {
+        if !is_metric_name(namespace) {
+            error(
+                "prometheus.namespace".to_string(),
+                format!("{:?} is not a valid metric name prefix", namespace),
+            );
+        }
+    }
+    let mut variable_labels: HashSet<&str> = telemetry::VARIABLE_LABELS.into();
+    if config.prometheus.threshold_labels {
+        variable_labels.extend(telemetry::THRESHOLD_LABELS);
+    }
+    for name in config.prometheus.const_labels.keys() {
+        let location = format!("prometheus.const_labels.{}", name);
+        if !is_metric_name(name) || name.starts_with("__") {
+            error(location, "is not a valid label name".to_string());
+        } else if variable_labels.contains(name.as_str()) {
+            error(location, "is already a label of the metrics".to_string());
+        } else if config.prometheus.push.is_some() && (name == "job" || name == "instance") {
+            error(location, "is set by prometheus.push".to_string());
+        }
+    }
+
+    let mut chain_ids = HashSet::new();
+    for (i, chain_config) in config.chains.iter().enumerate() {
+        let chain_location = format!("chains[{}] ({})", i, chain_config.id);
+        if chain_config.id.is_empty() {
+            error(
+                format!("{}.id", chain_location),
+                "must not be empty".to_string(),
+            );
+        } else if !chain_ids.insert(&chain_config.id) {
+            error(
+                format!("{}.id", chain_location),
+                format!(
+                    "chain id {:?} is configured more than once",
+                    chain_config.id
+                ),
+            );
+        }
+
+        if chain_config.connect_timeout.is_zero() {
+            error(
+                format!("{}.connect_timeout", chain_location),
+                "must not be zero".to_string(),
+            );
+        }
+        if chain_config.timeout.is_zero() {
+            error(
+                format!("{}.timeout", chain_location),
+                "must not be zero".to_string(),
+            );
+        }
+
+        let mut channels = HashSet::new();
+        for (j, chain_channel) in chain_config.channels.iter().enumerate() {
+            let location = format!(
+                "{}.channels[{}] ({}/{})",
+                chain_location, j, chain_channel.port_id, chain_channel.channel_id
+            );
+            if !channels.insert((&chain_channel.port_id, &chain_channel.channel_id)) {
+                error(
+                    location.clone(),
+                    "channel is configured more than once on this chain".to_string(),
+                );
+            }
+            if let Err(message) = chain_channel.min_total.parse::<Count>() {
+                error(format!("{}.min_total", location), message);
+            }
+            if let Some(min_unrelayed_acks) = &chain_channel.min_unrelayed_acks {
+                if let Err(message) = min_unrelayed_acks.parse::<Count>() {
+                    error(format!("{}.min_unrelayed_acks", location), message);
+                }
+            }
+            if let Some(x) = &chain_channel.min_time_before_client_expiration {
+                if duration_str::parse(x).is_err() {
+                    error(
+                        format!("{}.min_time_before_client_expiration", location),
+                        format!("{:?} is not a duration", x),
+                    );
+                }
+            }
+            if chain_channel.refresh.is_zero() {
+                error(
+                    format!("{}.refresh", location),
+                    "must not be zero".to_string(),
+                );
+            }
+        }
+
+        if let Some(discovery) = &chain_config.discovery {
+            let location = format!("{}.discovery", chain_location);
+            if let Err(message) = discovery.min_total.parse::<Count>() {
+                error(format!("{}.min_total", location), message);
+            }
+            if let Some(x) = &discovery.min_time_before_client_expiration {
+                if duration_str::parse(x).is_err() {
+                    error(
+                        format!("{}.min_time_before_client_expiration", location),
+                        format!("{:?} is not a duration", x),
+                    );
+                }
+            }
+            if discovery.refresh.is_zero() {
+                error(
+                    format!("{}.refresh", location),
+                    "must not be zero".to_string(),
+                );
+            }
+            if discovery.interval.is_zero() {
+                error(
+                    format!("{}.interval", location),
+                    "must not be zero".to_string(),
+                );
+            }
+        }
+    }
+
+    // channels pointing at chains which are not configured only lose the
+    // trackers querying the destination
+    for (i, chain_config) in config.chains.iter().enumerate() {
+        for (j, chain_channel) in chain_config.channels.iter().enumerate() {
+            if chain_ids.contains(&chain_channel.destination_chain_id) {
+                continue;
+            }
+            let location = format!(
+                "chains[{}] ({}).channels[{}] ({}/{}).destination_chain_id",
+                i, chain_config.id, j, chain_channel.port_id, chain_channel.channel_id
+            );
+            let message = if chain_channel.timeout_window.is_some() {
+                "chain is not configured, unreceived packets, unrelayed acknowledgements and packet timeouts are not tracked"
+            } else {
+                "chain is not configured, unreceived packets and unrelayed acknowledgements are not tracked"
+            };
+            issues.push(Issue {
+                severity: Severity::Warning,
+                location,
+                message: format!("{:?} {}", chain_channel.destination_chain_id, message),
+            });
+        }
+    }
+
+    if config.prometheus.reset.is_some() {
+        issues.push(Issue {
+            severity: Severity::Warning,
+            location: "prometheus.reset".to_string(),
+            message: if config.prometheus.series_ttl.is_some() {
+                "is deprecated and ignored in favour of prometheus.series_ttl".to_string()
+            } else {
+                "is deprecated, use prometheus.series_ttl".to_string()
+            },
+        });
+    }
+    if config.otel.is_some() && !cfg!(feature = "otel") {
+        issues.push(Issue {
+            severity: Severity::Warning,
+            location: "otel".to_string(),
+            message: "is ignored, ibc-watcher is built without the otel feature".to_string(),
+        });
+    }
+    // series refreshed less often than the ttl disappear between refreshes
+    if let Some(ttl) = config.prometheus.series_ttl() {
+        if NODE_SYNC_REFRESH >= ttl {
+            issues.push(Issue {
+                severity: Severity::Warning,
+                location: "prometheus.series_ttl".to_string(),
+                message: format!(
+                    "is not longer than the node sync refresh ({:?}), ibc_query_node_sync_status expires between refreshes",
+                    NODE_SYNC_REFRESH
+                ),
+            });
+        }
+        for (i, chain_config) in config.chains.iter().enumerate() {
+            let chain_location = format!("chains[{}] ({})", i, chain_config.id);
+            let refreshes = chain_config
+                .channels
+                .iter()
+                .enumerate()
+                .map(|(j, x)| {
+                    (
+                        format!(
+                            "{}.channels[{}] ({}/{}).refresh",
+                            chain_location, j, x.port_id, x.channel_id
+                        ),
+                        x.refresh,
+                    )
+                })
+                .chain(
+                    chain_config
+                        .discovery
+                        .as_ref()
+                        .map(|x| (format!("{}.discovery.refresh", chain_location), x.refresh)),
+                );
+            for (location, refresh) in refreshes {
+                if refresh >= ttl {
+                    issues.push(Issue {
+                        severity: Severity::Warning,
+                        location,
+                        message: format!(
+                            "is not shorter than prometheus.series_ttl ({:?}), its series expire between refreshes",
+                            ttl
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+/// Reloads the config file on SIGHUP or when it changes on disk and sends
+/// every config which loads successfully. An invalid config is logged and
+/// skipped, so the running one stays in effect.
+pub fn watch(path: PathBuf) -> mpsc::Receiver<Config> {
+    let (sender, receiver) = mpsc::channel(1);
+
+    tokio::task::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup()).expect("SIGHUP handler can be installed");
+        let mut watch_interval = tokio::time::interval(WATCH_INTERVAL);
+        let mut modified = modified_time(&path);
+
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {
+                    info!("received SIGHUP, reloading {}", path.display());
+                    modified = modified_time(&path);
+                }
+                _ = watch_interval.tick() => {
+                    let current = modified_time(&path);
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                    info!("{} changed, reloading", path.display());
+                }
+            }
+
+            match load(&path) {
+                Ok(config) => {
+                    if sender.send(config).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => error!("could not reload config, keeping the running one: {}", e),
+            }
+        }
+    });
+
+    receiver
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|x| x.modified()).ok()
 }
 
 /// Serialize the given `Config` as TOML to the given config file.
@@ -115,7 +860,7 @@ pub(crate) fn store_writer(config: &Config, mut writer: impl Write) -> Result<()
 
 #[cfg(test)]
 mod tests {
-    use super::{load, store_writer};
+    use super::{load, read, store_writer, validate, Severity};
     use test_log::test;
 
     #[test]
@@ -154,4 +899,75 @@ mod tests {
         let mut buffer = Vec::new();
         store_writer(&config, &mut buffer).unwrap();
     }
+
+    #[test]
+    fn validate_reports_all_issues() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/config/fixtures/chains-invalid.toml"
+        );
+
+        let config = read(path).expect("could not parse config");
+        let issues: Vec<(Severity, String)> = validate(&config)
+            .into_iter()
+            .map(|x| (x.severity, x.location))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (Severity::Error, "prometheus.host".to_string()),
+                (Severity::Error, "alerts.severities.ibc_bogus".to_string()),
+                (Severity::Error, "alerts.webhooks[0].url".to_string()),
+                (
+                    Severity::Error,
+                    "alerts.webhooks[1].routing_key".to_string()
+                ),
+                (Severity::Error, "prometheus.push.interval".to_string()),
+                (Severity::Error, "prometheus.push.username".to_string()),
+                (Severity::Error, "prometheus.namespace".to_string()),
+                (
+                    Severity::Error,
+                    "prometheus.const_labels.chain_id".to_string()
+                ),
+                (Severity::Error, "prometheus.const_labels.job".to_string()),
+                (
+                    Severity::Error,
+                    "chains[0] (chain_A).channels[0] (transfer/channel-0).min_time_before_client_expiration".to_string()
+                ),
+                (
+                    Severity::Error,
+                    "chains[0] (chain_A).channels[1] (transfer/channel-0)".to_string()
+                ),
+                (
+                    Severity::Error,
+                    "chains[0] (chain_A).channels[1] (transfer/channel-0).min_unrelayed_acks"
+                        .to_string()
+                ),
+                (
+                    Severity::Error,
+                    "chains[0] (chain_A).channels[1] (transfer/channel-0).refresh".to_string()
+                ),
+                (
+                    Severity::Error,
+                    "chains[1] (chain_B).discovery.min_total".to_string()
+                ),
+                (Severity::Error, "chains[2] (chain_B).id".to_string()),
+                (
+                    Severity::Warning,
+                    "chains[0] (chain_A).channels[1] (transfer/channel-0).destination_chain_id"
+                        .to_string()
+                ),
+                (Severity::Warning, "prometheus.reset".to_string()),
+                (
+                    Severity::Warning,
+                    "chains[0] (chain_A).channels[0] (transfer/channel-0).refresh".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    "chains[1] (chain_B).discovery.refresh".to_string()
+                ),
+            ]
+        );
+        assert!(load(path).is_err());
+    }
 }
@@ -0,0 +1,358 @@
+//! The Grafana dashboard, built from the collectors of the telemetry module so
+//! it always shows the metrics of the running version
+use crate::{
+    config::{Config, PrometheusConfig},
+    rules::const_label_matchers,
+    telemetry::{Metrics, THRESHOLD_LABELS},
+};
+use prometheus::core::Collector;
+use prometheus::proto::MetricType;
+use serde_json::{json, Value};
+
+const UID: &str = "cfac43ed978";
+const TITLE: &str = "IBC Watcher Status Dashboard";
+/// Panels per row of the grid, and their size
+const PANELS_PER_ROW: u64 = 2;
+const PANEL_WIDTH: u64 = 12;
+const PANEL_HEIGHT: u64 = 10;
+
+/// The labels shown in the legend of a channel series
+const CHANNEL_LABELS: &[&str] = &["chain_id", "port_id", "channel_id", "destination_chain_id"];
+
+/// A metric as the dashboard shows it
+#[derive(Clone, Debug, PartialEq)]
+struct Metric {
+    name: String,
+    help: String,
+    labels: Vec<String>,
+    metric_type: MetricType,
+}
+
+fn metrics(collectors: &Metrics) -> Vec<Metric> {
+    collectors
+        .collectors()
+        .iter()
+        .flat_map(|collector| {
+            let types: Vec<MetricType> = collector
+                .collect()
+                .iter()
+                .map(|x| x.get_field_type())
+                .collect();
+            collector
+                .desc()
+                .into_iter()
+                .zip(types)
+                .map(|(desc, metric_type)| Metric {
+                    name: desc.fq_name.clone(),
+                    help: desc.help.clone(),
+                    labels: desc.variable_labels.clone(),
+                    metric_type,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The dashboard with a panel per metric, repeated for every chain. Given a
+/// config, the metrics carry its namespace and the chains to choose from are
+/// the configured ones instead of the ones found in Prometheus, and the
+/// queries match its constant labels.
+pub fn generate(config: Option<&Config>) -> Value {
+    let default_config = PrometheusConfig::default();
+    let prometheus_config = config.map_or(&default_config, |x| &x.prometheus);
+    let collectors = Metrics::new(prometheus_config);
+    let matchers: Vec<String> = ["chain_id=~\"$chain_id\"".to_string()]
+        .into_iter()
+        .chain(const_label_matchers(prometheus_config))
+        .collect();
+    let selector = format!("{{{}}}", matchers.join(","));
+    let mut panels = vec![json!({
+        "collapsed": false,
+        "gridPos": { "h": 1, "w": 24, "x": 0, "y": 0 },
+        "id": 1,
+        "panels": [],
+        "repeat": "chain_id",
+        "title": "$chain_id",
+        "type": "row",
+    })];
+    for (index, metric) in metrics(&collectors).iter().enumerate() {
+        let index = index as u64;
+        panels.push(panel(
+            metric,
+            &selector,
+            index + 2,
+            json!({
+                "h": PANEL_HEIGHT,
+                "w": PANEL_WIDTH,
+                "x": index % PANELS_PER_ROW * PANEL_WIDTH,
+                "y": 1 + index / PANELS_PER_ROW * PANEL_HEIGHT,
+            }),
+        ));
+    }
+
+    json!({
+        "annotations": {
+            "list": [{
+                "builtIn": 1,
+                "datasource": "-- Grafana --",
+                "enable": true,
+                "hide": true,
+                "iconColor": "rgba(0, 211, 255, 1)",
+                "name": "Annotations & Alerts",
+                "type": "dashboard",
+            }],
+        },
+        "description": format!(
+            "IBC watcher {} metrics of cosmos-sdk and cometbft based chains, generated by ibc-watcher gen-dashboard",
+            env!("CARGO_PKG_VERSION")
+        ),
+        "editable": true,
+        "graphTooltip": 1,
+        "links": [],
+        "panels": panels,
+        "refresh": "1m",
+        "schemaVersion": 34,
+        "tags": ["Blockchain", "IBC"],
+        "templating": {
+            "list": [
+                datasource_variable(),
+                chain_variable(config, &collectors.ibc_query_node_sync_status.desc()[0].fq_name),
+            ],
+        },
+        "time": { "from": "now-3h", "to": "now" },
+        "timezone": "",
+        "title": TITLE,
+        "uid": UID,
+        "version": 1,
+    })
+}
+
+pub fn to_json(dashboard: &Value) -> String {
+    serde_json::to_string_pretty(dashboard).expect("dashboard can be serialized")
+}
+
+fn panel(metric: &Metric, selector: &str, id: u64, grid_pos: Value) -> Value {
+    let (expr, title) = match metric.metric_type {
+        MetricType::COUNTER => (
+            format!("increase({}{}[$__rate_interval])", metric.name, selector),
+            format!("{} (increase)", metric.name),
+        ),
+        MetricType::HISTOGRAM => {
+            let by: Vec<&str> = metric
+                .labels
+                .iter()
+                .map(String::as_str)
+                .filter(|x| *x != "chain_id")
+                .chain(["le"])
+                .collect();
+            (
+                format!(
+                    "histogram_quantile(0.95, sum by ({}) (rate({}_bucket{}[$__rate_interval])))",
+                    by.join(", "),
+                    metric.name,
+                    selector
+                ),
+                format!("{} (p95)", metric.name),
+            )
+        }
+        _ => (format!("{}{}", metric.name, selector), metric.name.clone()),
+    };
+    let unit = if metric.name.ends_with("_seconds") || metric.name.ends_with("_before_expire") {
+        "s"
+    } else {
+        "short"
+    };
+    json!({
+        "datasource": { "type": "prometheus", "uid": "$DS" },
+        "description": metric.help,
+        "fieldConfig": {
+            "defaults": {
+                "color": { "mode": "palette-classic" },
+                "custom": {
+                    "drawStyle": "line",
+                    "fillOpacity": 10,
+                    "lineWidth": 1,
+                    "showPoints": "never",
+                    "spanNulls": true,
+                },
+                "min": 0,
+                "unit": unit,
+            },
+            "overrides": [],
+        },
+        "gridPos": grid_pos,
+        "id": id,
+        "options": {
+            "legend": {
+                "calcs": ["lastNotNull", "max"],
+                "displayMode": "table",
+                "placement": "bottom",
+            },
+            "tooltip": { "mode": "multi" },
+        },
+        "targets": [{
+            "datasource": { "type": "prometheus", "uid": "$DS" },
+            "expr": expr,
+            "legendFormat": legend(&metric.labels),
+            "refId": "A",
+        }],
+        "title": title,
+        "type": "timeseries",
+    })
+}
+
+/// `port/channel -> destination` for the series of a channel, followed by
+/// the other labels except the thresholds
+fn legend(labels: &[String]) -> String {
+    let has = |label: &str| labels.iter().any(|x| x == label);
+    let mut parts = Vec::new();
+    if has("port_id") && has("channel_id") {
+        parts.push("{{port_id}}/{{channel_id}}".to_string());
+        if has("destination_chain_id") {
+            parts.push("-> {{destination_chain_id}}".to_string());
+        }
+    }
+    for label in labels.iter() {
+        if !CHANNEL_LABELS.contains(&label.as_str()) && !THRESHOLD_LABELS.contains(&label.as_str())
+        {
+            parts.push(format!("{{{{{}}}}}", label));
+        }
+    }
+    if parts.is_empty() {
+        "{{chain_id}}".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+fn datasource_variable() -> Value {
+    json!({
+        "current": { "selected": false, "text": "Prometheus", "value": "Prometheus" },
+        "hide": 0,
+        "includeAll": false,
+        "label": "Datasource",
+        "multi": false,
+        "name": "DS",
+        "options": [],
+        "query": "prometheus",
+        "refresh": 1,
+        "regex": "",
+        "type": "datasource",
+    })
+}
+
+/// The configured chains, or the chains found in Prometheus without a config
+fn chain_variable(config: Option<&Config>, chain_metric: &str) -> Value {
+    match config {
+        Some(config) => {
+            let chain_ids: Vec<&str> = config.chains.iter().map(|x| x.id.as_str()).collect();
+            let options: Vec<Value> = chain_ids
+                .iter()
+                .map(|x| json!({ "selected": true, "text": x, "value": x }))
+                .collect();
+            json!({
+                "current": { "selected": true, "text": chain_ids, "value": chain_ids },
+                "hide": 0,
+                "includeAll": true,
+                "label": "Chain ID",
+                "multi": true,
+                "name": "chain_id",
+                "options": options,
+                "query": chain_ids.join(","),
+                "type": "custom",
+            })
+        }
+        None => json!({
+            "current": { "selected": true, "text": ["All"], "value": ["$__all"] },
+            "datasource": { "type": "prometheus", "uid": "$DS" },
+            "definition": format!("label_values({}, chain_id)", chain_metric),
+            "hide": 0,
+            "includeAll": true,
+            "label": "Chain ID",
+            "multi": true,
+            "name": "chain_id",
+            "options": [],
+            "query": {
+                "query": format!("label_values({}, chain_id)", chain_metric),
+                "refId": "Prometheus-chain_id-Variable-Query",
+            },
+            "refresh": 1,
+            "regex": "",
+            "sort": 1,
+            "type": "query",
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    #[test]
+    fn shipped_dashboard_is_up_to_date() {
+        let shipped = include_str!("../grafana/ibc-watcher.json");
+        assert_eq!(
+            shipped.trim_end(),
+            to_json(&generate(None)),
+            "regenerate it with ibc-watcher gen-dashboard -o grafana/ibc-watcher.json"
+        );
+    }
+
+    #[test]
+    fn panel_per_metric_and_configured_chains() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/config/fixtures/chains.toml"
+        );
+        let config = config::load(path).expect("could not parse config");
+        let dashboard = generate(Some(&config));
+
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(
+            panels.len(),
+            metrics(&Metrics::new(&config.prometheus)).len() + 1
+        );
+        let expr = |title: &str| {
+            panels
+                .iter()
+                .find(|x| x["title"] == title)
+                .map(|x| x["targets"][0]["expr"].clone())
+        };
+        assert_eq!(
+            expr("ibc_count"),
+            Some(json!(
+                "ibc_count{chain_id=~\"$chain_id\",environment=\"testnet\"}"
+            ))
+        );
+        assert_eq!(
+            expr("ibc_packets_timed_out_total (increase)"),
+            Some(json!(
+                "increase(ibc_packets_timed_out_total{chain_id=~\"$chain_id\",environment=\"testnet\"}[$__rate_interval])"
+            ))
+        );
+        let chains = &dashboard["templating"]["list"][1];
+        assert_eq!(chains["type"], "custom");
+        assert_eq!(chains["query"], "chain_A,chain_B");
+    }
+
+    #[test]
+    fn legend_skips_threshold_labels() {
+        let labels = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            legend(&labels(&[
+                "chain_id",
+                "port_id",
+                "channel_id",
+                "destination_chain_id",
+                "min_total"
+            ])),
+            "{{port_id}}/{{channel_id}} -> {{destination_chain_id}}"
+        );
+        assert_eq!(
+            legend(&labels(&["task", "chain_id", "port_id", "channel_id"])),
+            "{{port_id}}/{{channel_id}} {{task}}"
+        );
+        assert_eq!(legend(&labels(&["chain_id"])), "{{chain_id}}");
+    }
+}
@@ -19,19 +19,47 @@ define_error! {
             [ TraceError<std::num::ParseIntError> ]
             |_| { "invalid number" },
 
-        ConfigParseU64
-            [ TraceError<std::num::ParseIntError> ]
-            |_| { "invalid number" },
+        ConfigInvalid
+            { issues: Vec<String> }
+            |e| { format_args!("invalid configuration: {}", e.issues.join("; ")) },
 
         GrpcTransport
             [ TraceError<TransportError> ]
             |_| { "error in underlying transport when making gRPC call" },
 
+        NoGrpcEndpoint
+            |_| { "no gRPC endpoint configured" },
+
+        OtlpExporter
+            { reason: String }
+            |e| { format_args!("cannot start the OTLP exporter: {}", e.reason) },
+
         GetPacketCommitmentsTotal
             |_| { format_args!(
                 "error in getting packet commitments total")
             },
 
+        GetChannel
+            |_| { format_args!(
+                "error in getting channel")
+            },
+
+        GetChannelCounterparty
+            |_| { format_args!(
+                "channel has no counterparty channel yet")
+            },
+
+        GetSendPacket
+            { sequence: u64 }
+            |e| { format_args!(
+                "error in getting send_packet event of sequence {}", e.sequence)
+            },
+
+        GetConnection
+            |_| { format_args!(
+                "error in getting connection")
+            },
+
         GetChannelClientState
             |_| { format_args!(
                 "error in getting channel client state")
@@ -48,3 +76,9 @@ define_error! {
             },
     }
 }
+
+/// The first line of an error, leaving out the cause and location traced
+/// below it
+pub fn summary(e: &impl std::fmt::Display) -> String {
+    e.to_string().lines().next().unwrap_or_default().to_string()
+}
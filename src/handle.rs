@@ -1,386 +1,2100 @@
-use crate::{config, query, telemetry::*};
+use crate::{
+    alert::{self, Alert},
+    config::{self, NODE_SYNC_REFRESH},
+    query, status,
+    supervisor::{Supervisor, TaskLabels},
+    telemetry::*,
+};
 use duration_str::parse;
-use ibc_relayer_types::Height;
+use ibc_proto::ibc::core::{
+    channel::v1::State as ChannelState, connection::v1::State as ConnectionState,
+};
+use ibc_relayer_types::{core::ics24_host::identifier::ChainId, Height};
 use log::{error, info, warn};
-use std::{sync::Arc, time::Duration};
-use tendermint_rpc::Url;
-
-pub async fn ibc_status_collector(config: config::Config) {
-    for chain_config in config.chains.iter() {
-        let grpc_addr = chain_config.grpc_addr.clone();
-        let chain_id = chain_config.id.clone();
-        let halt = Arc::new(tokio::sync::Mutex::new(false));
-        tokio::task::spawn(track_query_node_sync_status(
-            grpc_addr.clone(),
-            chain_id.clone(),
+use std::future::Future;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, watch};
+use tracing::Instrument;
+
+/// Upper bound of `send_packet` lookups per refresh, so a large backlog is
+/// resolved over several refreshes instead of flooding the node
+const MAX_PACKET_TIMEOUT_LOOKUPS: usize = 50;
+
+/// Runs the trackers of the config and keeps them in line with every reloaded
+/// config received on `reloads`, until `shutdown` completes and all trackers
+/// are stopped.
+pub async fn ibc_status_collector(
+    mut config: config::Config,
+    mut reloads: mpsc::Receiver<config::Config>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let mut collector = Collector::default();
+    alert::configure(config.alerts.as_ref());
+    collector.apply(&config);
+    let mut expiry = expiry_interval(&config.prometheus);
+    let mut heartbeat_interval = tokio::time::interval(status::HEARTBEAT_INTERVAL);
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => {
+                info!("stopping all trackers");
+                status::collector_stopped();
+                collector.shutdown().await;
+                return;
+            }
+            Some(new_config) = reloads.recv() => {
+                if new_config == config {
+                    continue;
+                }
+                info!("config changed, updating trackers");
+                if new_config.prometheus.host != config.prometheus.host
+                    || new_config.prometheus.port != config.prometheus.port
+                    || new_config.prometheus.readiness_staleness
+                        != config.prometheus.readiness_staleness
+                    || new_config.prometheus.namespace != config.prometheus.namespace
+                    || new_config.prometheus.const_labels != config.prometheus.const_labels
+                    || new_config.prometheus.threshold_labels
+                        != config.prometheus.threshold_labels
+                    || new_config.prometheus.push != config.prometheus.push
+                {
+                    warn!("changes to the prometheus host, port, readiness_staleness, namespace, const_labels, threshold_labels and push take effect after a restart");
+                }
+                if new_config.otel != config.otel {
+                    warn!("changes to otel take effect after a restart");
+                }
+                if new_config.prometheus.series_ttl() != config.prometheus.series_ttl() {
+                    expiry = expiry_interval(&new_config.prometheus);
+                }
+                alert::configure(new_config.alerts.as_ref());
+                collector.apply(&new_config);
+                config = new_config;
+            }
+            _ = heartbeat_interval.tick() => status::collector_heartbeat(),
+            _ = tick(&mut expiry) => {
+                if let Some(ttl) = config.prometheus.series_ttl() {
+                    let expired = metrics().expire(ttl);
+                    if expired > 0 {
+                        info!("removed {} series not updated within {:?}", expired, ttl);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Checks for expired series twice per ttl, so a series is removed at most
+/// half a ttl after it expired
+fn expiry_interval(config: &config::PrometheusConfig) -> Option<tokio::time::Interval> {
+    config
+        .series_ttl()
+        .map(|ttl| tokio::time::interval(ttl / 2))
+}
+
+/// The span of one polling cycle of a tracker, the parent of the spans of the
+/// gRPC calls made during the cycle
+fn cycle_span(labels: TaskLabels) -> tracing::Span {
+    tracing::info_span!(
+        "cycle",
+        otel.name = labels.task,
+        chain_id = %labels.chain_id,
+        port_id = %labels.port_id,
+        channel_id = %labels.channel_id,
+    )
+}
+
+/// The config of a channel as its trackers receive it, with the thresholds
+/// parsed once when it is built
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackedChannel {
+    pub config: config::Channel,
+    pub min_total: config::Count,
+    pub min_unrelayed_acks: config::Count,
+    /// 1/3 of the trusting period of the client when not set
+    pub min_time_before_client_expiration: Option<Duration>,
+}
+
+impl TrackedChannel {
+    /// Fails on thresholds which `config::validate` rejects
+    pub fn new(chain_channel: &config::Channel) -> Result<Self, String> {
+        Ok(Self {
+            config: chain_channel.clone(),
+            min_total: chain_channel.min_total.parse()?,
+            min_unrelayed_acks: chain_channel.min_unrelayed_acks().parse()?,
+            min_time_before_client_expiration: chain_channel
+                .min_time_before_client_expiration
+                .as_ref()
+                .map(|x| parse(x).map_err(|_| format!("{:?} is not a duration", x)))
+                .transpose()?,
+        })
+    }
+}
+
+/// Waits for the next refresh of a channel tracker and returns the latest
+/// config of the channel. A reloaded refresh restarts the interval.
+async fn next_refresh(
+    interval: &mut tokio::time::Interval,
+    channel_config: &watch::Receiver<TrackedChannel>,
+) -> TrackedChannel {
+    interval.tick().await;
+    let current = channel_config.borrow().clone();
+    let refresh = current.config.refresh;
+    if interval.period() != refresh {
+        *interval = tokio::time::interval_at(tokio::time::Instant::now() + refresh, refresh);
+    }
+    current
+}
+
+/// Ticks the interval, never completes without one
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The running trackers, grouped per chain and channel so they can be started
+/// and stopped as the config changes. Dropping a group aborts its tasks.
+#[derive(Default)]
+struct Collector {
+    /// one client per chain, shared by all of its trackers and by the
+    /// trackers of the channels pointing at it
+    clients: HashMap<String, query::ChainClient>,
+    chains: HashMap<String, ChainTrackers>,
+}
+
+struct ChainTrackers {
+    config: config::ChainConfig,
+    halt: Arc<tokio::sync::Mutex<bool>>,
+    /// node sync
+    tasks: Supervisor,
+    discovery: Option<DiscoveryTracker>,
+    channels: HashMap<(String, String), ChannelTrackers>,
+}
+
+/// The channel discovery of a chain, which receives the config of the chain
+/// on every reload instead of being restarted
+struct DiscoveryTracker {
+    updates: watch::Sender<config::ChainConfig>,
+    tasks: Supervisor,
+}
+
+/// The trackers of a channel, which receive the config of the channel on
+/// every reload. Only a new destination or a new client restarts them, so
+/// changed thresholds keep the state of the trackers.
+struct ChannelTrackers {
+    config: config::Channel,
+    updates: watch::Sender<TrackedChannel>,
+    tasks: Supervisor,
+}
+
+impl Collector {
+    /// Starts the trackers of new chains and channels, stops the ones which
+    /// were removed and restarts the ones whose endpoints or destination
+    /// changed. Other trackers keep running with the new thresholds, and
+    /// series whose labels stay the same are left in place.
+    fn apply(&mut self, config: &config::Config) {
+        // chains whose client was created, replaced or dropped, channels
+        // pointing at them need their trackers restarted
+        let mut changed_clients = HashSet::new();
+        let mut clients = HashMap::new();
+        for chain_config in config.chains.iter() {
+            let running = self
+                .chains
+                .get(&chain_config.id)
+                .filter(|chain| {
+                    chain.config.grpc_addrs() == chain_config.grpc_addrs()
+                        && chain.config.connect_timeout == chain_config.connect_timeout
+                        && chain.config.timeout == chain_config.timeout
+                })
+                .and_then(|_| self.clients.get(&chain_config.id));
+            if let Some(client) = running {
+                clients.insert(chain_config.id.clone(), client.clone());
+                continue;
+            }
+            changed_clients.insert(chain_config.id.clone());
+            match query::ChainClient::from_config(chain_config) {
+                Ok(client) => {
+                    clients.insert(chain_config.id.clone(), client);
+                }
+                Err(e) => error!("invalid grpc_addr of chain ({}): {}", chain_config.id, e),
+            }
+        }
+        changed_clients.extend(
+            self.clients
+                .keys()
+                .filter(|chain_id| !clients.contains_key(*chain_id))
+                .cloned(),
+        );
+        self.clients = clients;
+
+        let removed_chains: Vec<String> = self
+            .chains
+            .keys()
+            .filter(|chain_id| !self.clients.contains_key(*chain_id))
+            .cloned()
+            .collect();
+        for chain_id in removed_chains {
+            info!("stopping trackers of chain ({})", chain_id);
+            self.chains.remove(&chain_id);
+            ibc_chain_metrics_remover(&chain_id);
+            status::remove_chain(&chain_id);
+            alert::forget_chain(&chain_id);
+        }
+
+        for chain_config in config.chains.iter() {
+            let chain_id = &chain_config.id;
+            let Some(client) = self.clients.get(chain_id) else {
+                continue;
+            };
+            // known to /readyz before its first query
+            status::update_chain(chain_id, |_| {});
+            let restart = match self.chains.get(chain_id) {
+                Some(_) => changed_clients.contains(chain_id),
+                None => true,
+            };
+
+            let mut old_channels = HashMap::new();
+            if restart {
+                if let Some(mut old_chain) = self.chains.remove(chain_id) {
+                    info!("restarting trackers of chain ({})", chain_id);
+                    old_channels = std::mem::take(&mut old_chain.channels);
+                }
+                let halt = Arc::new(tokio::sync::Mutex::new(false));
+                let mut tasks = Supervisor::default();
+                {
+                    let (client, chain_id, halt) = (client.clone(), chain_id.clone(), halt.clone());
+                    tasks.spawn(
+                        TaskLabels::chain("node_sync_status", &chain_id),
+                        move || {
+                            track_query_node_sync_status(
+                                client.clone(),
+                                chain_id.clone(),
+                                halt.clone(),
+                            )
+                        },
+                    );
+                }
+                self.chains.insert(
+                    chain_id.clone(),
+                    ChainTrackers {
+                        config: chain_config.clone(),
+                        halt,
+                        tasks,
+                        discovery: None,
+                        channels: HashMap::new(),
+                    },
+                );
+            }
+            let chain = self.chains.get_mut(chain_id).unwrap();
+            if !restart {
+                old_channels = std::mem::take(&mut chain.channels);
+                chain.config = chain_config.clone();
+            }
+
+            // the discovered channels keep the clients they were started
+            // with, so any new client restarts the discovery
+            match (&chain_config.discovery, &chain.discovery) {
+                (None, _) => chain.discovery = None,
+                (Some(_), Some(discovery)) if changed_clients.is_empty() => {
+                    discovery.updates.send_if_modified(|x| {
+                        let modified = x != chain_config;
+                        *x = chain_config.clone();
+                        modified
+                    });
+                }
+                (Some(_), _) => {
+                    if chain.discovery.is_some() {
+                        info!("restarting discovery of chain ({})", chain_id);
+                    }
+                    let (updates, chain_config) = watch::channel(chain_config.clone());
+                    let mut tasks = Supervisor::default();
+                    let (client, clients, chain_id, halt) = (
+                        client.clone(),
+                        self.clients.clone(),
+                        chain_id.clone(),
+                        chain.halt.clone(),
+                    );
+                    tasks.spawn(TaskLabels::chain("discovery", &chain_id), move || {
+                        discover_channels(
+                            client.clone(),
+                            clients.clone(),
+                            chain_id.clone(),
+                            chain_config.clone(),
+                            halt.clone(),
+                        )
+                    });
+                    chain.discovery = Some(DiscoveryTracker { updates, tasks });
+                }
+            }
+
+            for chain_channel in chain_config.channels.iter() {
+                // rejected by config::validate, stopped below if it was running
+                let tracked_channel = match TrackedChannel::new(chain_channel) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        error!(
+                            "invalid thresholds of channel_id ({}) on ({}): {}",
+                            chain_channel.channel_id, chain_id, e
+                        );
+                        continue;
+                    }
+                };
+                let key = (
+                    chain_channel.port_id.clone(),
+                    chain_channel.channel_id.clone(),
+                );
+                let old_channel = old_channels.remove(&key);
+                if let Some(mut old_channel) = old_channel {
+                    if !restart
+                        && old_channel.config.destination_chain_id
+                            == chain_channel.destination_chain_id
+                        && !changed_clients.contains(&chain_channel.destination_chain_id)
+                    {
+                        if old_channel.config != *chain_channel {
+                            info!(
+                                "updating trackers of channel_id ({}) on ({})",
+                                chain_channel.channel_id, chain_id
+                            );
+                            remove_stale_channel_metrics(
+                                chain_id,
+                                &old_channel.config,
+                                Some(chain_channel),
+                            );
+                            old_channel.updates.send_replace(tracked_channel);
+                            old_channel.config = chain_channel.clone();
+                        }
+                        chain.channels.insert(key, old_channel);
+                        continue;
+                    }
+                    remove_stale_channel_metrics(
+                        chain_id,
+                        &old_channel.config,
+                        Some(chain_channel),
+                    );
+                }
+                info!(
+                    "starting trackers of channel_id ({}) with destination_chain_id {} on ({})",
+                    chain_channel.channel_id, chain_channel.destination_chain_id, chain_id
+                );
+                let (updates, channel_config) = watch::channel(tracked_channel);
+                let tasks = spawn_channel_trackers(
+                    client,
+                    &self.clients,
+                    chain_id,
+                    &channel_config,
+                    &chain.halt,
+                );
+                chain.channels.insert(
+                    key,
+                    ChannelTrackers {
+                        config: chain_channel.clone(),
+                        updates,
+                        tasks,
+                    },
+                );
+            }
+
+            for (_, old_channel) in old_channels {
+                info!(
+                    "stopping trackers of channel_id ({}) on ({})",
+                    old_channel.config.channel_id, chain_id
+                );
+                remove_stale_channel_metrics(chain_id, &old_channel.config, None);
+            }
+        }
+    }
+
+    /// Stops all trackers and waits until they are gone
+    async fn shutdown(self) {
+        for (_, chain) in self.chains {
+            chain.tasks.shutdown().await;
+            if let Some(discovery) = chain.discovery {
+                discovery.tasks.shutdown().await;
+            }
+            for (_, channel) in chain.channels {
+                channel.tasks.shutdown().await;
+            }
+        }
+    }
+}
+
+/// Removes the series of a stopped channel which its new trackers, if any,
+/// will not overwrite
+fn remove_stale_channel_metrics(
+    chain_id: &str,
+    old: &config::Channel,
+    new: Option<&config::Channel>,
+) {
+    let port_id = &old.port_id;
+    let channel_id = &old.channel_id;
+    match new {
+        Some(new) if new.destination_chain_id == old.destination_chain_id => {
+            // thresholds which are labels mean a new series for a new
+            // threshold, the threshold gauges are simply overwritten
+            if metrics().threshold_labels && new.min_total != old.min_total {
+                ibc_status_metrics_remover(chain_id, port_id, channel_id);
+            }
+            if metrics().threshold_labels
+                && new.min_time_before_client_expiration != old.min_time_before_client_expiration
+            {
+                ibc_client_metrics_remover(chain_id, port_id, channel_id);
+            }
+        }
+        _ => {
+            ibc_channel_metrics_remover(chain_id, port_id, channel_id);
+            status::remove_channel(chain_id, port_id, channel_id);
+            alert::forget_channel(chain_id, port_id, channel_id);
+        }
+    }
+}
+
+/// Spawns all trackers of a channel under a supervisor of their own
+fn spawn_channel_trackers(
+    client: &query::ChainClient,
+    clients: &HashMap<String, query::ChainClient>,
+    chain_id: &str,
+    channel_config: &watch::Receiver<TrackedChannel>,
+    halt: &Arc<tokio::sync::Mutex<bool>>,
+) -> Supervisor {
+    let chain_channel = &channel_config.borrow().config.clone();
+    let mut tasks = Supervisor::default();
+    let labels = |task| {
+        TaskLabels::channel(
+            task,
+            chain_id,
+            &chain_channel.port_id,
+            &chain_channel.channel_id,
+        )
+    };
+    let destination_client = clients.get(&chain_channel.destination_chain_id).cloned();
+    let commitments = Commitments::new(client, channel_config);
+    {
+        let (client, destination_client, commitments, chain_id, channel_config, halt) = (
+            client.clone(),
+            destination_client.clone(),
+            commitments.clone(),
+            chain_id.to_string(),
+            channel_config.clone(),
             halt.clone(),
-        ));
-        for chain_channel in chain_config.channels.clone().iter() {
-            tokio::task::spawn(track_ibc_status(
-                grpc_addr.clone(),
+        );
+        tasks.spawn(labels("ibc_status"), move || {
+            track_ibc_status(
+                client.clone(),
+                destination_client.clone(),
+                commitments.clone(),
                 chain_id.clone(),
-                chain_channel.clone(),
+                channel_config.clone(),
                 halt.clone(),
-            ));
-            tokio::task::spawn(track_ibc_client_status(
-                grpc_addr.clone(),
-                chain_id.clone(),
-                chain_channel.clone(),
-            ));
+            )
+        });
+    }
+    if let Some(destination_client) = destination_client {
+        {
+            let (client, destination_client, commitments, chain_id, channel_config, halt) = (
+                client.clone(),
+                destination_client.clone(),
+                commitments.clone(),
+                chain_id.to_string(),
+                channel_config.clone(),
+                halt.clone(),
+            );
+            tasks.spawn(labels("ibc_unrelayed_acks"), move || {
+                track_ibc_unrelayed_acks(
+                    client.clone(),
+                    destination_client.clone(),
+                    commitments.clone(),
+                    chain_id.clone(),
+                    channel_config.clone(),
+                    halt.clone(),
+                )
+            });
+        }
+        {
+            let (client, chain_id, channel_config, halt) = (
+                client.clone(),
+                chain_id.to_string(),
+                channel_config.clone(),
+                halt.clone(),
+            );
+            tasks.spawn(labels("ibc_packet_timeouts"), move || {
+                track_ibc_packet_timeouts(
+                    client.clone(),
+                    destination_client.clone(),
+                    commitments.clone(),
+                    chain_id.clone(),
+                    channel_config.clone(),
+                    halt.clone(),
+                )
+            });
+        }
+    }
+    {
+        let (client, chain_id, channel_config) =
+            (client.clone(), chain_id.to_string(), channel_config.clone());
+        tasks.spawn(labels("ibc_client_status"), move || {
+            track_ibc_client_status(client.clone(), chain_id.clone(), channel_config.clone())
+        });
+    }
+    {
+        let (client, chain_id, channel_config) =
+            (client.clone(), chain_id.to_string(), channel_config.clone());
+        tasks.spawn(labels("ibc_channel_state"), move || {
+            track_ibc_channel_state(client.clone(), chain_id.clone(), channel_config.clone())
+        });
+    }
+    tasks
+}
+
+/// The packet commitment sequences of a channel, listed at most once per
+/// refresh and shared by the trackers which need them, so the paginated
+/// listing is not repeated by each of them
+#[derive(Clone)]
+pub struct Commitments {
+    client: query::ChainClient,
+    port_id: String,
+    channel_id: String,
+    /// a listing younger than half a refresh is reused, so each refresh
+    /// of the trackers gets a listing of its own
+    channel_config: watch::Receiver<TrackedChannel>,
+    latest: Arc<tokio::sync::Mutex<Option<Listing>>>,
+}
+
+struct Listing {
+    at: Instant,
+    endpoint: String,
+    sequences: Arc<Vec<u64>>,
+    /// the sequences not yet received on the destination chain
+    unreceived: Option<Arc<Vec<u64>>>,
+}
+
+impl Commitments {
+    pub fn new(
+        client: &query::ChainClient,
+        channel_config: &watch::Receiver<TrackedChannel>,
+    ) -> Self {
+        let chain_channel = &channel_config.borrow().config.clone();
+        Self {
+            client: client.clone(),
+            port_id: chain_channel.port_id.clone(),
+            channel_id: chain_channel.channel_id.clone(),
+            channel_config: channel_config.clone(),
+            latest: Default::default(),
+        }
+    }
+
+    /// The latest listing, fetched first unless a recent one exists. The lock
+    /// is held while fetching, so trackers asking at the same time wait for
+    /// the same listing instead of fetching their own.
+    pub async fn get(&self) -> query::Queried<Arc<Vec<u64>>> {
+        let mut latest = self.latest.lock().await;
+        let max_age = self.channel_config.borrow().config.refresh / 2;
+        if let Some(listing) = latest.as_ref().filter(|x| x.at.elapsed() < max_age) {
+            return query::Queried {
+                endpoint: listing.endpoint.clone(),
+                result: Ok(listing.sequences.clone()),
+            };
+        }
+        let queried = self
+            .client
+            .get_packet_commitment_sequences(self.port_id.clone(), self.channel_id.clone())
+            .await
+            .and_then(|sequences| Ok(Arc::new(sequences)));
+        if let Ok(sequences) = &queried.result {
+            *latest = Some(Listing {
+                at: Instant::now(),
+                endpoint: queried.endpoint.clone(),
+                sequences: sequences.clone(),
+                unreceived: None,
+            });
+        }
+        queried
+    }
+
+    /// The sequences of a listing which are not received on the destination
+    /// chain yet, asked once per listing as well. `counterparty` is the
+    /// (port_id, channel_id) of the channel end on the destination chain.
+    pub async fn unreceived(
+        &self,
+        destination_client: &query::ChainClient,
+        counterparty: &(String, String),
+        sequences: &Arc<Vec<u64>>,
+    ) -> anyhow::Result<Arc<Vec<u64>>> {
+        let mut latest = self.latest.lock().await;
+        let listing = latest
+            .as_mut()
+            .filter(|x| Arc::ptr_eq(&x.sequences, sequences));
+        if let Some(unreceived) = listing.as_ref().and_then(|x| x.unreceived.clone()) {
+            return Ok(unreceived);
+        }
+        let unreceived = if sequences.is_empty() {
+            Arc::default()
+        } else {
+            Arc::new(
+                destination_client
+                    .get_unreceived_packets(
+                        counterparty.0.clone(),
+                        counterparty.1.clone(),
+                        sequences.to_vec(),
+                    )
+                    .await
+                    .result?,
+            )
+        };
+        if let Some(listing) = listing {
+            listing.unreceived = Some(unreceived.clone());
+        }
+        Ok(unreceived)
+    }
+}
+
+/// Periodically enumerates the open channels of the chain and spawns the
+/// trackers of every channel not tracked yet. The destination chain id of a
+/// channel is resolved through its connection's client state. Channels which
+/// are no longer open or listed lose their trackers and series. A reloaded
+/// config of the chain is applied to the discovered channels right away.
+pub async fn discover_channels(
+    client: query::ChainClient,
+    clients: HashMap<String, query::ChainClient>,
+    chain_id: String,
+    mut chain_config: watch::Receiver<config::ChainConfig>,
+    halt: Arc<tokio::sync::Mutex<bool>>,
+) {
+    // trackers of the discovered channels, aborted together with this task
+    let mut tracked: HashMap<(String, String), ChannelTrackers> = HashMap::new();
+    let mut discovery_interval = tokio::time::interval(config::default::discovery_interval());
+
+    loop {
+        tokio::select! {
+            _ = discovery_interval.tick() => {}
+            Ok(()) = chain_config.changed() => {}
+        }
+        let current = chain_config.borrow_and_update().clone();
+        let Some(discovery) = current.discovery else {
+            return;
+        };
+        if discovery_interval.period() != discovery.interval {
+            discovery_interval = tokio::time::interval(discovery.interval);
+            // the first tick completes immediately
+            discovery_interval.tick().await;
+        }
+        // channels listed in the config keep their own thresholds
+        let configured: HashMap<(String, String), config::Channel> = current
+            .channels
+            .into_iter()
+            .map(|x| ((x.port_id.clone(), x.channel_id.clone()), x))
+            .collect();
+
+        let cycle = cycle_span(TaskLabels::chain("discovery", &chain_id));
+        async {
+            // channels listed since the last scan are tracked by the
+            // collector, the others follow the thresholds of the discovery
+            let listed: Vec<(String, String)> = tracked
+                .keys()
+                .filter(|key| configured.contains_key(*key))
+                .cloned()
+                .collect();
+            for key in listed {
+                let channel = tracked.remove(&key).unwrap();
+                channel.tasks.shutdown().await;
+                remove_stale_channel_metrics(&chain_id, &channel.config, configured.get(&key));
+            }
+            for channel in tracked.values_mut() {
+                let chain_channel = discovery.channel(
+                    &channel.config.port_id,
+                    &channel.config.channel_id,
+                    &channel.config.destination_chain_id,
+                );
+                if chain_channel == channel.config {
+                    continue;
+                }
+                match TrackedChannel::new(&chain_channel) {
+                    Ok(tracked_channel) => {
+                        remove_stale_channel_metrics(
+                            &chain_id,
+                            &channel.config,
+                            Some(&chain_channel),
+                        );
+                        channel.updates.send_replace(tracked_channel);
+                        channel.config = chain_channel;
+                    }
+                    Err(e) => error!("invalid thresholds of discovery on ({}): {}", chain_id, e),
+                }
+            }
+
+            let channels = match client.get_channels().await.result {
+                Ok(channels) => channels,
+                Err(e) => {
+                    error!(
+                        "cannot list channels on ({}): {} and retry next discovery",
+                        chain_id, e
+                    );
+                    return;
+                }
+            };
+            let channels: Vec<_> = channels
+                .into_iter()
+                .filter(|x| x.state == ChannelState::Open as i32 && discovery.matches_port(&x.port_id))
+                .collect();
+            let open: HashSet<(String, String)> = channels
+                .iter()
+                .map(|x| (x.port_id.clone(), x.channel_id.clone()))
+                .collect();
+            let gone: Vec<(String, String)> = tracked
+                .keys()
+                .filter(|key| !open.contains(*key))
+                .cloned()
+                .collect();
+            for key in gone {
+                let channel = tracked.remove(&key).unwrap();
+                info!(
+                    "stopping trackers of channel_id ({}) on ({}), it is no longer open",
+                    channel.config.channel_id, chain_id
+                );
+                channel.tasks.shutdown().await;
+                remove_stale_channel_metrics(&chain_id, &channel.config, None);
+            }
+            // connections are shared by channels, resolve each one once per scan
+            let mut counterparty_chain_ids: HashMap<String, String> = HashMap::new();
+
+            for channel in channels {
+                if configured.contains_key(&(channel.port_id.clone(), channel.channel_id.clone()))
+                    || tracked.contains_key(&(channel.port_id.clone(), channel.channel_id.clone()))
+                {
+                    continue;
+                }
+                let Some(connection_id) = channel.connection_hops.first() else {
+                    continue;
+                };
+                let destination_chain_id = match counterparty_chain_ids.get(connection_id) {
+                    Some(destination_chain_id) => destination_chain_id.clone(),
+                    None => match client
+                        .get_connection_counterparty_chain_id(connection_id.clone())
+                        .await
+                        .result
+                    {
+                        Ok(destination_chain_id) => {
+                            counterparty_chain_ids
+                                .insert(connection_id.clone(), destination_chain_id.clone());
+                            destination_chain_id
+                        }
+                        Err(e) => {
+                            warn!(
+                                "cannot resolve the counterparty chain of connection_id ({}) on ({}): {} and retry next discovery",
+                                connection_id, chain_id, e
+                            );
+                            continue;
+                        }
+                    },
+                };
+
+                info!(
+                    "Discovered channel_id ({}) on port_id ({}) with destination_chain_id {} on ({})",
+                    channel.channel_id, channel.port_id, destination_chain_id, chain_id
+                );
+                let chain_channel =
+                    discovery.channel(&channel.port_id, &channel.channel_id, &destination_chain_id);
+                let tracked_channel = match TrackedChannel::new(&chain_channel) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        error!("invalid thresholds of discovery on ({}): {}", chain_id, e);
+                        continue;
+                    }
+                };
+                let (updates, channel_config) = watch::channel(tracked_channel);
+                let tasks =
+                    spawn_channel_trackers(&client, &clients, &chain_id, &channel_config, &halt);
+                tracked.insert(
+                    (channel.port_id, channel.channel_id),
+                    ChannelTrackers {
+                        config: chain_channel,
+                        updates,
+                        tasks,
+                    },
+                );
+            }
+        }
+        .instrument(cycle)
+        .await;
+    }
+}
+
+pub async fn track_ibc_client_status(
+    client: query::ChainClient,
+    chain_id: String,
+    channel_config: watch::Receiver<TrackedChannel>,
+) {
+    let chain_channel = channel_config.borrow().config.clone();
+    let port_id = &chain_channel.port_id;
+    let channel_id = &chain_channel.channel_id;
+    let destination_chain_id = &chain_channel.destination_chain_id;
+    let mut collect_interval = tokio::time::interval(chain_channel.refresh);
+    let mut last_channel_client_state_height: Option<Height> = None;
+    let mut last_channel_client_consensus_state_duration: Option<Duration> = None;
+    let mut trusting_period: Option<Duration> = None;
+
+    loop {
+        let current = next_refresh(&mut collect_interval, &channel_config).await;
+
+        let cycle = cycle_span(TaskLabels::channel(
+            "ibc_client_status",
+            &chain_id,
+            port_id,
+            channel_id,
+        ));
+        async {
+            if trusting_period.is_none() {
+                info!("The trusting_period is not set, fetching from the chain");
+                let queried = client
+                    .get_trusting_period(port_id.into(), channel_id.into())
+                    .await;
+                trusting_period = match queried.result {
+                    Ok(d) => {
+                        ibc_query_status_setter(
+                            &chain_id,
+                            port_id,
+                            channel_id,
+                            destination_chain_id,
+                            &queried.endpoint,
+                            0,
+                        );
+                        status::update_channel(&chain_id, &chain_channel, |x| x.succeeded("trusting_period"));
+                        info!("The trusting_period={:?} with channel_id ({}) with destination_chain_id {} on ({})", d, channel_id, destination_chain_id, chain_id);
+                        Some(d)
+                    }
+                    Err(e) => {
+                        error!("{} and retry next refresh", e);
+                        status::update_channel(&chain_id, &chain_channel, |x| x.failed("trusting_period", &e));
+                        ibc_query_status_setter(
+                            &chain_id,
+                            port_id,
+                            channel_id,
+                            destination_chain_id,
+                            &queried.endpoint,
+                            1,
+                        );
+                        return;
+                    }
+                }
+            }
+            let Some(trusting_period) = trusting_period else {
+                return;
+            };
+
+            // 1/3 of the trusting_period when not set
+            let min_time_before_client_expiration = current
+                .min_time_before_client_expiration
+                .unwrap_or(trusting_period / 3);
+
+            let queried = client
+                .get_channel_client_state(port_id.into(), channel_id.into())
+                .await;
+            let channel_client_state = match queried.result {
+                Ok(client_state) => {
+                    ibc_query_status_setter(
+                        &chain_id,
+                        port_id,
+                        channel_id,
+                        destination_chain_id,
+                        &queried.endpoint,
+                        0,
+                    );
+                    status::update_channel(&chain_id, &chain_channel, |x| x.succeeded("client_state"));
+                    client_state
+                }
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    status::update_channel(&chain_id, &chain_channel, |x| x.failed("client_state", &e));
+                    ibc_query_status_setter(
+                        &chain_id,
+                        port_id,
+                        channel_id,
+                        destination_chain_id,
+                        &queried.endpoint,
+                        1,
+                    );
+                    return;
+                }
+            };
+            let channel_client_state_height = channel_client_state.client_state.latest_height();
+            let client_id = channel_client_state.client_id.to_string();
+
+            // the chain's own verdict, which also catches frozen clients
+            match client.get_client_status(client_id.clone()).await.result {
+                Ok(client_status) => {
+                    if client_status != query::ClientStatus::Active {
+                        warn!(
+                            "The client_id ({}) of channel_id ({}) with destination_chain_id {} on ({}) is {}",
+                            client_id, channel_id, destination_chain_id, chain_id, client_status
+                        );
+                    }
+                    ibc_client_onchain_status_setter(
+                        &chain_id,
+                        port_id,
+                        channel_id,
+                        destination_chain_id,
+                        &client_id,
+                        client_status as i64,
+                    );
+                    alert::update(
+                        Alert::channel("ibc_client_onchain_status", &chain_id, &chain_channel)
+                            .value(format!("client {} is {}", client_id, client_status)),
+                        matches!(
+                            client_status,
+                            query::ClientStatus::Expired | query::ClientStatus::Frozen
+                        ),
+                    );
+                    status::update_channel(&chain_id, &chain_channel, |x| {
+                        x.client_id = Some(client_id.clone());
+                        x.client_status = Some(client_status.to_string());
+                    });
+                }
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    status::update_channel(&chain_id, &chain_channel, |x| x.failed("client_status", &e));
+                }
+            }
+
+            if last_channel_client_state_height.is_none_or(|x| channel_client_state_height > x) {
+                let queried = client
+                    .get_latest_channel_client_consensus_state_duration(
+                        port_id.into(),
+                        channel_id.into(),
+                        channel_client_state_height,
+                    )
+                    .await;
+                let channel_client_consensus_state_duration = match queried.result {
+                    Ok(duration) => {
+                        ibc_query_status_setter(
+                            &chain_id,
+                            port_id,
+                            channel_id,
+                            destination_chain_id,
+                            &queried.endpoint,
+                            0,
+                        );
+                        status::update_channel(&chain_id, &chain_channel, |x| x.succeeded("consensus_state"));
+                        info!(
+                                "The channel_client_consensus_state_duration={:?} with channel_id ({}) with destination_chain_id {} on ({})",
+                                duration, channel_id, destination_chain_id, chain_id
+                            );
+                        last_channel_client_state_height = Some(channel_client_state_height);
+                        duration
+                    }
+                    Err(e) => {
+                        error!("{} and retry next refresh", e);
+                        status::update_channel(&chain_id, &chain_channel, |x| x.failed("consensus_state", &e));
+                        ibc_query_status_setter(
+                            &chain_id,
+                            port_id,
+                            channel_id,
+                            destination_chain_id,
+                            &queried.endpoint,
+                            1,
+                        );
+                        return;
+                    }
+                };
+
+                last_channel_client_consensus_state_duration =
+                    Some(channel_client_consensus_state_duration);
+            }
+            let Some(channel_client_consensus_state_duration) =
+                last_channel_client_consensus_state_duration
+            else {
+                return;
+            };
+
+            update_ibc_client_status(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                min_time_before_client_expiration,
+                trusting_period,
+                channel_client_consensus_state_duration,
+            );
+            status::update_channel(&chain_id, &chain_channel, |x| {
+                x.client_expiry_time =
+                    Some(UNIX_EPOCH + channel_client_consensus_state_duration + trusting_period);
+                x.trusting_period = Some(trusting_period);
+                x.min_time_before_client_expiration = Some(min_time_before_client_expiration);
+            });
+        }
+        .instrument(cycle)
+        .await;
+    }
+}
+
+fn update_ibc_client_status(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    min_time_before_client_expiration: Duration,
+    trusting_period: Duration,
+    last_channel_client_consensus_state_duration: Duration,
+) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+    let expiry_time = last_channel_client_consensus_state_duration + trusting_period;
+    let min_time_before_client_expiration_str =
+        min_time_before_client_expiration.as_secs().to_string() + "s";
+    ibc_client_expiry_threshold_setter(
+        chain_id,
+        port_id,
+        channel_id,
+        destination_chain_id,
+        min_time_before_client_expiration,
+    );
+
+    if expiry_time.gt(&now) {
+        let time_before_expire = (expiry_time - now).as_secs().try_into().unwrap();
+        ibc_client_time_before_expire_setter(
+            chain_id,
+            port_id,
+            channel_id,
+            destination_chain_id,
+            &min_time_before_client_expiration_str,
+            time_before_expire,
+        );
+        if (expiry_time - now).gt(&min_time_before_client_expiration) {
+            ibc_client_status_setter(
+                chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                &min_time_before_client_expiration_str,
+                0,
+            );
+        } else {
+            ibc_client_status_setter(
+                chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                &min_time_before_client_expiration_str,
+                1,
+            );
+        }
+    } else {
+        ibc_client_time_before_expire_setter(
+            chain_id,
+            port_id,
+            channel_id,
+            destination_chain_id,
+            &min_time_before_client_expiration_str,
+            0,
+        );
+        ibc_client_status_setter(
+            chain_id,
+            port_id,
+            channel_id,
+            destination_chain_id,
+            &min_time_before_client_expiration_str,
+            1,
+        );
+    }
+
+    let time_before_expire = expiry_time.saturating_sub(now);
+    alert::update(
+        Alert {
+            alert: "ibc_client_status",
+            chain_id: chain_id.to_string(),
+            port_id: port_id.to_string(),
+            channel_id: channel_id.to_string(),
+            destination_chain_id: destination_chain_id.to_string(),
+            value: format!("{}s before client expiration", time_before_expire.as_secs()),
+            threshold: min_time_before_client_expiration_str,
+            ..Default::default()
+        },
+        time_before_expire <= min_time_before_client_expiration,
+    );
+}
+
+/// Tracks the packet commitments of the channel.
+///
+/// When the destination chain is configured as well, the commitments are
+/// split into packets not yet received on the destination and packets
+/// received but not yet acknowledged back. The age of the oldest commitment
+/// is measured from when the watcher first saw it.
+pub async fn track_ibc_status(
+    client: query::ChainClient,
+    destination_client: Option<query::ChainClient>,
+    commitments: Commitments,
+    chain_id: String,
+    channel_config: watch::Receiver<TrackedChannel>,
+    halt: Arc<tokio::sync::Mutex<bool>>,
+) {
+    let chain_channel = channel_config.borrow().config.clone();
+    let port_id = &chain_channel.port_id;
+    let channel_id = &chain_channel.channel_id;
+    let destination_chain_id = &chain_channel.destination_chain_id;
+    let mut counterparty: Option<(String, String)> = None;
+    let mut pending_packets = PendingPackets::default();
+    let mut collect_interval = tokio::time::interval(chain_channel.refresh);
+
+    loop {
+        let current = next_refresh(&mut collect_interval, &channel_config).await;
+        let min_total = current.min_total;
+        if *halt.lock().await {
+            // remove the metrics to avoid false alarms
+            ibc_count_remover(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                min_total,
+            );
+            ibc_unreceived_packets_remover(&chain_id, port_id, channel_id, destination_chain_id);
+            ibc_oldest_packet_age_remover(&chain_id, port_id, channel_id, destination_chain_id);
+            continue;
+        }
+
+        let cycle = cycle_span(TaskLabels::channel(
+            "ibc_status",
+            &chain_id,
+            port_id,
+            channel_id,
+        ));
+        async {
+            let queried = commitments.get().await;
+            let sequences = match queried.result {
+                Ok(sequences) => {
+                    ibc_query_status_setter(
+                        &chain_id,
+                        port_id,
+                        channel_id,
+                        destination_chain_id,
+                        &queried.endpoint,
+                        0,
+                    );
+                    status::update_channel(&chain_id, &chain_channel, |x| x.succeeded("packet_commitments"));
+                    sequences
+                }
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    status::update_channel(&chain_id, &chain_channel, |x| x.failed("packet_commitments", &e));
+                    ibc_query_status_setter(
+                        &chain_id,
+                        port_id,
+                        channel_id,
+                        destination_chain_id,
+                        &queried.endpoint,
+                        1,
+                    );
+                    return;
+                }
+            };
+            let total = sequences.len() as u64;
+            info!(
+                "The latest total={} with channel_id ({}) with destination_chain_id {} on ({})",
+                total, channel_id, destination_chain_id, chain_id
+            );
+            if total < min_total.get() {
+                ibc_status_setter(
+                    &chain_id,
+                    port_id,
+                    channel_id,
+                    destination_chain_id,
+                    min_total,
+                    0,
+                );
+            } else {
+                warn!("The current total {} with channel_id ({}) is higher than {} with destination_chain_id {} on ({})", total, channel_id, min_total, destination_chain_id, chain_id);
+                ibc_status_setter(
+                    &chain_id,
+                    port_id,
+                    channel_id,
+                    destination_chain_id,
+                    min_total,
+                    1,
+                );
+            }
+            ibc_count_setter(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                min_total,
+                total.try_into().unwrap(),
+            );
+            ibc_count_threshold_setter(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                min_total,
+            );
+            alert::update(
+                Alert::channel("ibc_status", &chain_id, &chain_channel)
+                    .value(format!("{} packet commitments", total))
+                    .threshold(min_total.to_string()),
+                total >= min_total.get(),
+            );
+            status::update_channel(&chain_id, &chain_channel, |x| x.commitments = Some(total));
+
+            let now = SystemTime::now();
+            pending_packets.update(&sequences, now);
+            let oldest_packet_age = pending_packets.oldest_age(now);
+            let packet_age_status = current.config.max_packet_age.map(|max_packet_age| {
+                if oldest_packet_age > max_packet_age {
+                    warn!("The oldest packet age {:?} with channel_id ({}) is higher than {:?} with destination_chain_id {} on ({})", oldest_packet_age, channel_id, max_packet_age, destination_chain_id, chain_id);
+                    1
+                } else {
+                    0
+                }
+            });
+            ibc_oldest_packet_age_setter(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                oldest_packet_age.as_secs().try_into().unwrap(),
+                packet_age_status,
+            );
+            alert::update(
+                Alert::channel("ibc_packet_age_status", &chain_id, &chain_channel)
+                    .value(format!("oldest packet {}s old", oldest_packet_age.as_secs()))
+                    .threshold(
+                        current
+                            .config
+                            .max_packet_age
+                            .map(|x| format!("{}s", x.as_secs()))
+                            .unwrap_or_default(),
+                    ),
+                packet_age_status == Some(1),
+            );
+
+            if let Some(destination_client) = &destination_client {
+                match count_unreceived_packets(
+                    &client,
+                    destination_client,
+                    &commitments,
+                    port_id,
+                    channel_id,
+                    &sequences,
+                    &mut counterparty,
+                )
+                .await
+                {
+                    Ok((unreceived, received_unacknowledged)) => {
+                        info!(
+                            "The unreceived={} and received_unacknowledged={} with channel_id ({}) with destination_chain_id {} on ({})",
+                            unreceived, received_unacknowledged, channel_id, destination_chain_id, chain_id
+                        );
+                        ibc_unreceived_packets_setter(
+                            &chain_id,
+                            port_id,
+                            channel_id,
+                            destination_chain_id,
+                            unreceived.try_into().unwrap(),
+                            received_unacknowledged.try_into().unwrap(),
+                        );
+                    }
+                    Err(e) => {
+                        error!(
+                            "cannot count unreceived packets of channel_id ({}) on ({}): {} and retry next refresh",
+                            channel_id, destination_chain_id, e
+                        );
+                        ibc_unreceived_packets_remover(
+                            &chain_id,
+                            port_id,
+                            channel_id,
+                            destination_chain_id,
+                        );
+                    }
+                }
+            }
+        }
+        .instrument(cycle)
+        .await;
+    }
+}
+
+/// Tracks the acknowledgements written on the destination chain for packets
+/// of the channel which have not been relayed back to the source chain yet.
+pub async fn track_ibc_unrelayed_acks(
+    client: query::ChainClient,
+    destination_client: query::ChainClient,
+    commitments: Commitments,
+    chain_id: String,
+    channel_config: watch::Receiver<TrackedChannel>,
+    halt: Arc<tokio::sync::Mutex<bool>>,
+) {
+    let chain_channel = channel_config.borrow().config.clone();
+    let port_id = &chain_channel.port_id;
+    let channel_id = &chain_channel.channel_id;
+    let destination_chain_id = &chain_channel.destination_chain_id;
+    let mut counterparty: Option<(String, String)> = None;
+    let mut collect_interval = tokio::time::interval(chain_channel.refresh);
+
+    loop {
+        let current = next_refresh(&mut collect_interval, &channel_config).await;
+        let min_unrelayed_acks = current.min_unrelayed_acks;
+        if *halt.lock().await {
+            // remove the metrics to avoid false alarms
+            ibc_unrelayed_acks_remover(&chain_id, port_id, channel_id, destination_chain_id);
+            continue;
+        }
+
+        let cycle = cycle_span(TaskLabels::channel(
+            "ibc_unrelayed_acks",
+            &chain_id,
+            port_id,
+            channel_id,
+        ));
+        async {
+            let unrelayed = match count_unrelayed_acks(
+                &client,
+                &destination_client,
+                &commitments,
+                port_id,
+                channel_id,
+                &mut counterparty,
+            )
+            .await
+            {
+                Ok(unrelayed) => unrelayed,
+                Err(e) => {
+                    error!(
+                        "cannot count unrelayed acks of channel_id ({}) on ({}): {} and retry next refresh",
+                        channel_id, chain_id, e
+                    );
+                    ibc_unrelayed_acks_remover(&chain_id, port_id, channel_id, destination_chain_id);
+                    return;
+                }
+            };
+            info!(
+                "The unrelayed_acks={} with channel_id ({}) with destination_chain_id {} on ({})",
+                unrelayed, channel_id, destination_chain_id, chain_id
+            );
+
+            let status = if unrelayed < min_unrelayed_acks.get() {
+                0
+            } else {
+                warn!("The current unrelayed_acks {} with channel_id ({}) is higher than {} with destination_chain_id {} on ({})", unrelayed, channel_id, min_unrelayed_acks, destination_chain_id, chain_id);
+                1
+            };
+            ibc_unrelayed_acks_setter(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                unrelayed.try_into().unwrap(),
+                status,
+            );
+            alert::update(
+                Alert::channel("ibc_unrelayed_acks_status", &chain_id, &chain_channel)
+                    .value(format!("{} unrelayed acks", unrelayed))
+                    .threshold(min_unrelayed_acks.to_string()),
+                status == 1,
+            );
+        }
+        .instrument(cycle)
+        .await;
+    }
+}
+
+/// Tracks the outstanding packets of the channel against their timeouts on the
+/// destination chain. The timeout of each packet is read once from its
+/// `send_packet` event. Idle while the channel has no `timeout_window`.
+pub async fn track_ibc_packet_timeouts(
+    client: query::ChainClient,
+    destination_client: query::ChainClient,
+    commitments: Commitments,
+    chain_id: String,
+    channel_config: watch::Receiver<TrackedChannel>,
+    halt: Arc<tokio::sync::Mutex<bool>>,
+) {
+    let chain_channel = channel_config.borrow().config.clone();
+    let port_id = &chain_channel.port_id;
+    let channel_id = &chain_channel.channel_id;
+    let destination_chain_id = &chain_channel.destination_chain_id;
+    let destination_revision = ChainId::chain_version(destination_chain_id);
+    let mut counterparty: Option<(String, String)> = None;
+    let mut packet_timeouts = PacketTimeouts::default();
+    let mut collect_interval = tokio::time::interval(chain_channel.refresh);
+
+    loop {
+        let current = next_refresh(&mut collect_interval, &channel_config).await;
+        let Some(timeout_window) = current.config.timeout_window else {
+            ibc_packets_near_timeout_remover(&chain_id, port_id, channel_id, destination_chain_id);
+            alert::update(
+                Alert::channel("ibc_packets_near_timeout", &chain_id, &chain_channel),
+                false,
+            );
+            alert::update(
+                Alert::channel("ibc_packets_timed_out", &chain_id, &chain_channel),
+                false,
+            );
+            continue;
+        };
+        if *halt.lock().await {
+            // remove the metrics to avoid false alarms
+            ibc_packets_near_timeout_remover(&chain_id, port_id, channel_id, destination_chain_id);
+            continue;
+        }
+
+        let cycle = cycle_span(TaskLabels::channel(
+            "ibc_packet_timeouts",
+            &chain_id,
+            port_id,
+            channel_id,
+        ));
+        async {
+            let sequences = match commitments.get().await.result {
+                Ok(sequences) => sequences,
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    return;
+                }
+            };
+            if counterparty.is_none() {
+                match get_counterparty(&client, port_id, channel_id).await {
+                    Ok(x) => counterparty = Some(x),
+                    Err(e) => {
+                        error!("{} and retry next refresh", e);
+                        return;
+                    }
+                }
+            }
+            // packets received on the destination wait for their ack and
+            // cannot time out any more
+            let sequences = match commitments
+                .unreceived(&destination_client, counterparty.as_ref().unwrap(), &sequences)
+                .await
+            {
+                Ok(unreceived) => unreceived,
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    return;
+                }
+            };
+            packet_timeouts.retain(&sequences);
+            for sequence in packet_timeouts.unknown(&sequences, MAX_PACKET_TIMEOUT_LOOKUPS) {
+                match client
+                    .get_packet_timeout(port_id.into(), channel_id.into(), sequence)
+                    .await
+                    .result
+                {
+                    Ok(timeout) => packet_timeouts.insert(sequence, timeout),
+                    Err(e) => warn!(
+                        "{} with channel_id ({}) on ({}) and retry next refresh",
+                        e, channel_id, chain_id
+                    ),
+                }
+            }
+
+            let (height, time) = match destination_client.get_latest_height_and_time().await.result {
+                Ok(latest) => latest,
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    return;
+                }
+            };
+            let Ok(height) = Height::new(destination_revision, height.try_into().unwrap_or(0)) else {
+                return;
+            };
+
+            let (near_timeout, timed_out) = packet_timeouts.check(height, time, timeout_window);
+            if near_timeout > 0 || timed_out > 0 {
+                warn!(
+                    "{} packets near timeout and {} packets newly timed out with channel_id ({}) with destination_chain_id {} on ({})",
+                    near_timeout, timed_out, channel_id, destination_chain_id, chain_id
+                );
+            }
+            ibc_packets_near_timeout_setter(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                near_timeout.try_into().unwrap(),
+            );
+            ibc_packets_timed_out_incrementer(
+                &chain_id,
+                port_id,
+                channel_id,
+                destination_chain_id,
+                timed_out,
+            );
+            let window = format!("{}s", timeout_window.as_secs());
+            alert::update(
+                Alert::channel("ibc_packets_near_timeout", &chain_id, &chain_channel)
+                    .value(format!("{} packets near timeout", near_timeout))
+                    .threshold(window.clone()),
+                near_timeout > 0,
+            );
+            // past their timeout, until the relayer times them out on the
+            // source chain
+            let past_timeout = packet_timeouts.timed_out.len();
+            alert::update(
+                Alert::channel("ibc_packets_timed_out", &chain_id, &chain_channel)
+                    .value(format!("{} packets past their timeout", past_timeout))
+                    .threshold(window),
+                past_timeout > 0,
+            );
+        }
+        .instrument(cycle)
+        .await;
+    }
+}
+
+/// The timeouts of the outstanding packets of a channel
+#[derive(Debug, Default)]
+struct PacketTimeouts {
+    timeouts: HashMap<u64, query::PacketTimeout>,
+    /// packets seen before their timeout, only these are counted when they
+    /// pass it, so restarting the tracker does not count a packet again
+    pending: HashSet<u64>,
+    /// packets past their timeout
+    timed_out: HashSet<u64>,
+    /// last (height, time) of the destination chain, to estimate its block time
+    last_block: Option<(Height, Duration)>,
+    block_time: Option<Duration>,
+}
+
+impl PacketTimeouts {
+    /// Forgets the packets which are no longer outstanding
+    fn retain(&mut self, sequences: &[u64]) {
+        let outstanding: HashSet<u64> = sequences.iter().copied().collect();
+        self.timeouts
+            .retain(|sequence, _| outstanding.contains(sequence));
+        self.pending
+            .retain(|sequence| outstanding.contains(sequence));
+        self.timed_out
+            .retain(|sequence| outstanding.contains(sequence));
+    }
+
+    /// Up to `limit` outstanding packets whose timeout is not known yet
+    fn unknown(&self, sequences: &[u64], limit: usize) -> Vec<u64> {
+        sequences
+            .iter()
+            .copied()
+            .filter(|sequence| !self.timeouts.contains_key(sequence))
+            .take(limit)
+            .collect()
+    }
+
+    fn insert(&mut self, sequence: u64, timeout: query::PacketTimeout) {
+        self.timeouts.insert(sequence, timeout);
+    }
+
+    /// Returns the no. of packets timing out within the window and the no. of
+    /// packets which passed their timeout since the last check, given the
+    /// latest height and time of the destination chain. A packet already past
+    /// its timeout when it is first checked is not counted, its timeout may
+    /// have been counted before the tracker restarted.
+    ///
+    /// The time left until a timeout height is estimated from the block time
+    /// observed between checks.
+    fn check(&mut self, height: Height, time: Duration, window: Duration) -> (u64, u64) {
+        if let Some((last_height, last_time)) = self.last_block {
+            let blocks = height
+                .revision_height()
+                .saturating_sub(last_height.revision_height());
+            if height.revision_number() == last_height.revision_number() && blocks > 0 {
+                self.block_time = Some(nanos(
+                    time.saturating_sub(last_time).as_nanos() / u128::from(blocks),
+                ));
+            }
+        }
+        self.last_block = Some((height, time));
+
+        let mut near_timeout = 0;
+        let mut timed_out = 0;
+        for (sequence, timeout) in self.timeouts.iter() {
+            let height_passed = timeout.height.is_some_and(|x| height >= x);
+            let time_passed = timeout.timestamp.is_some_and(|x| time >= x);
+            if height_passed || time_passed {
+                if self.timed_out.insert(*sequence) && self.pending.remove(sequence) {
+                    timed_out += 1;
+                }
+                continue;
+            }
+            self.pending.insert(*sequence);
+
+            let time_left = timeout.timestamp.map(|x| x - time);
+            let height_time_left = timeout
+                .height
+                .filter(|x| x.revision_number() == height.revision_number())
+                .zip(self.block_time)
+                .map(|(x, block_time)| {
+                    nanos(
+                        block_time.as_nanos()
+                            * u128::from(x.revision_height() - height.revision_height()),
+                    )
+                });
+            if time_left
+                .into_iter()
+                .chain(height_time_left)
+                .min()
+                .is_some_and(|left| left <= window)
+            {
+                near_timeout += 1;
+            }
+        }
+        (near_timeout, timed_out)
+    }
+}
+
+/// A duration of `nanos` nanoseconds, saturating at `u64::MAX` of them
+fn nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+}
+
+/// Returns the no. of acknowledgements written on the destination chain which
+/// have not been relayed back to the source chain.
+///
+/// Only packets whose commitment still exists on the source can have an
+/// unrelayed acknowledgement, so the acknowledgements are looked up for those
+/// sequences only.
+async fn count_unrelayed_acks(
+    client: &query::ChainClient,
+    destination_client: &query::ChainClient,
+    commitments: &Commitments,
+    port_id: &str,
+    channel_id: &str,
+    counterparty: &mut Option<(String, String)>,
+) -> anyhow::Result<u64> {
+    if counterparty.is_none() {
+        *counterparty = Some(get_counterparty(client, port_id, channel_id).await?);
+    }
+    let (counterparty_port_id, counterparty_channel_id) = counterparty.clone().unwrap();
+
+    let sequences = commitments.get().await.result?;
+    if sequences.is_empty() {
+        return Ok(0);
+    }
+    let acks = destination_client
+        .get_packet_acknowledgement_sequences(
+            counterparty_port_id,
+            counterparty_channel_id,
+            sequences.to_vec(),
+        )
+        .await
+        .result?;
+    if acks.is_empty() {
+        return Ok(0);
+    }
+
+    Ok(client
+        .get_unreceived_acks(port_id.into(), channel_id.into(), acks)
+        .await
+        .result?
+        .len() as u64)
+}
+
+/// Remembers when each outstanding packet commitment of a channel was first
+/// seen by the watcher
+#[derive(Debug, Default)]
+struct PendingPackets {
+    first_seen: HashMap<u64, SystemTime>,
+}
+
+impl PendingPackets {
+    /// Forgets the packets which are no longer outstanding and starts the clock
+    /// of the new ones
+    fn update(&mut self, sequences: &[u64], now: SystemTime) {
+        let outstanding: HashSet<u64> = sequences.iter().copied().collect();
+        self.first_seen
+            .retain(|sequence, _| outstanding.contains(sequence));
+        for sequence in outstanding {
+            self.first_seen.entry(sequence).or_insert(now);
         }
     }
-    if let Some(interval) = config.prometheus.reset {
-        let mut reset_interval = tokio::time::interval(interval);
-        loop {
-            reset_interval.tick().await;
-            info!("reset metrics!");
-            IBC_STATUS_COLLECTOR.reset();
-            IBC_COUNT_COLLECTOR.reset();
-            IBC_QUERY_STATUS_COLLECTOR.reset();
-        }
+
+    /// Age of the oldest outstanding packet, zero if there is none
+    fn oldest_age(&self, now: SystemTime) -> Duration {
+        self.first_seen
+            .values()
+            .min()
+            .and_then(|first_seen| now.duration_since(*first_seen).ok())
+            .unwrap_or_default()
     }
 }
 
-pub async fn track_ibc_client_status(
-    grpc_addr: Url,
+/// Returns the no. of packets not yet received on the destination chain and
+/// the no. of packets received there but not yet acknowledged on the source.
+///
+/// The counterparty (port_id, channel_id) is looked up once and cached.
+async fn count_unreceived_packets(
+    client: &query::ChainClient,
+    destination_client: &query::ChainClient,
+    commitments: &Commitments,
+    port_id: &str,
+    channel_id: &str,
+    sequences: &Arc<Vec<u64>>,
+    counterparty: &mut Option<(String, String)>,
+) -> anyhow::Result<(u64, u64)> {
+    if sequences.is_empty() {
+        return Ok((0, 0));
+    }
+    if counterparty.is_none() {
+        *counterparty = Some(get_counterparty(client, port_id, channel_id).await?);
+    }
+
+    let unreceived = commitments
+        .unreceived(
+            destination_client,
+            counterparty.as_ref().unwrap(),
+            sequences,
+        )
+        .await?
+        .len() as u64;
+
+    Ok((
+        unreceived,
+        (sequences.len() as u64).saturating_sub(unreceived),
+    ))
+}
+
+/// Fetches the counterparty (port_id, channel_id) of the channel
+async fn get_counterparty(
+    client: &query::ChainClient,
+    port_id: &str,
+    channel_id: &str,
+) -> anyhow::Result<(String, String)> {
+    let counterparty = client
+        .get_channel(port_id.into(), channel_id.into())
+        .await
+        .result?
+        .counterparty
+        .filter(|x| !x.channel_id.is_empty())
+        .ok_or_else(crate::error::Error::get_channel_counterparty)?;
+    Ok((counterparty.port_id, counterparty.channel_id))
+}
+
+/// Tracks the state of the channel and of its connection, so a closed channel
+/// does not pass for a healthy one without commitments.
+pub async fn track_ibc_channel_state(
+    client: query::ChainClient,
     chain_id: String,
-    chain_channel: config::Channel,
+    channel_config: watch::Receiver<TrackedChannel>,
 ) {
+    let chain_channel = channel_config.borrow().config.clone();
     let port_id = &chain_channel.port_id;
     let channel_id = &chain_channel.channel_id;
     let destination_chain_id = &chain_channel.destination_chain_id;
-    let refresh = &chain_channel.refresh;
-    let mut min_time_before_client_expiration: Option<Duration> = chain_channel
-        .min_time_before_client_expiration
-        .as_ref()
-        .map(|x| parse(x).unwrap());
-    let mut collect_interval = tokio::time::interval(refresh.to_owned());
-    let mut last_channel_client_state_height = Height::new(0, 1).unwrap();
-    let mut last_channel_client_consensus_state_duration: Option<Duration> = None;
-    let mut trusting_period: Option<Duration> = None;
+    let mut collect_interval = tokio::time::interval(chain_channel.refresh);
 
     loop {
-        collect_interval.tick().await;
+        next_refresh(&mut collect_interval, &channel_config).await;
 
-        if trusting_period.is_none() {
-            info!("The trusting_period is not set, fetching from the chain");
-            trusting_period = match query::get_trusting_period(
-                port_id.into(),
-                channel_id.into(),
-                grpc_addr.to_string(),
-            )
-            .await
-            {
-                Ok(d) => {
+        let cycle = cycle_span(TaskLabels::channel(
+            "ibc_channel_state",
+            &chain_id,
+            port_id,
+            channel_id,
+        ));
+        async {
+            let queried = client.get_channel(port_id.into(), channel_id.into()).await;
+            let channel = match queried.result {
+                Ok(channel) => {
                     ibc_query_status_setter(
                         &chain_id,
                         port_id,
                         channel_id,
                         destination_chain_id,
-                        &grpc_addr.to_string(),
+                        &queried.endpoint,
                         0,
                     );
-                    info!("The trusting_period={:?} with channel_id ({}) with destination_chain_id {} on ({})", d, channel_id, destination_chain_id, chain_id);
-                    Some(d)
+                    status::update_channel(&chain_id, &chain_channel, |x| x.succeeded("channel"));
+                    channel
                 }
                 Err(e) => {
                     error!("{} and retry next refresh", e);
+                    status::update_channel(&chain_id, &chain_channel, |x| x.failed("channel", &e));
                     ibc_query_status_setter(
                         &chain_id,
                         port_id,
                         channel_id,
                         destination_chain_id,
-                        &grpc_addr.to_string(),
+                        &queried.endpoint,
                         1,
                     );
-                    continue;
+                    return;
                 }
-            }
-        }
-
-        if min_time_before_client_expiration.is_none() {
-            info!("The min_time_before_client_expiration is not set, set it as 1/3 of trusting_period");
-            min_time_before_client_expiration = Some(trusting_period.unwrap() / 3);
-        }
-
-        let channel_client_state_height = match query::get_latest_channel_client_state_height(
-            port_id.into(),
-            channel_id.into(),
-            grpc_addr.to_string(),
-        )
-        .await
-        {
-            Ok(height) => {
-                ibc_query_status_setter(
-                    &chain_id,
-                    port_id,
-                    channel_id,
-                    destination_chain_id,
-                    &grpc_addr.to_string(),
-                    0,
-                );
-                height
-            }
-            Err(e) => {
-                error!("{} and retry next refresh", e);
-                ibc_query_status_setter(
-                    &chain_id,
-                    port_id,
-                    channel_id,
-                    destination_chain_id,
-                    &grpc_addr.to_string(),
-                    1,
+            };
+            let channel_state = ChannelState::try_from(channel.state)
+                .map(|x| x.as_str_name())
+                .unwrap_or("UNKNOWN");
+            let channel_open = channel.state == ChannelState::Open as i32;
+            if !channel_open {
+                warn!(
+                    "The channel_id ({}) with destination_chain_id {} on ({}) is {}",
+                    channel_id, destination_chain_id, chain_id, channel_state
                 );
-                continue;
             }
-        };
-
-        if channel_client_state_height.revision_height()
-            > last_channel_client_state_height.revision_height()
-        {
-            let channel_client_consensus_state_duration =
-                match query::get_latest_channel_client_consensus_state_duration(
-                    port_id.into(),
-                    channel_id.into(),
-                    channel_client_state_height,
-                    grpc_addr.to_string(),
-                )
-                .await
-                {
-                    Ok(duration) => {
-                        ibc_query_status_setter(
-                            &chain_id,
-                            port_id,
-                            channel_id,
-                            destination_chain_id,
-                            &grpc_addr.to_string(),
-                            0,
-                        );
-                        info!(
-                            "The channel_client_consensus_state_duration={:?} with channel_id ({}) with destination_chain_id {} on ({})",
-                            duration, channel_id, destination_chain_id, chain_id
-                        );
-                        last_channel_client_state_height = channel_client_state_height;
-                        duration
-                    }
-                    Err(e) => {
-                        error!("{} and retry next refresh", e);
-                        ibc_query_status_setter(
-                            &chain_id,
-                            port_id,
-                            channel_id,
-                            destination_chain_id,
-                            &grpc_addr.to_string(),
-                            1,
-                        );
-                        continue;
-                    }
-                };
-
-            last_channel_client_consensus_state_duration =
-                Some(channel_client_consensus_state_duration);
-        }
-
-        update_ibc_client_status(
-            &chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            min_time_before_client_expiration.unwrap(),
-            trusting_period.unwrap(),
-            last_channel_client_consensus_state_duration.unwrap(),
-        );
-    }
-}
-
-fn update_ibc_client_status(
-    chain_id: &str,
-    port_id: &str,
-    channel_id: &str,
-    destination_chain_id: &str,
-    min_time_before_client_expiration: Duration,
-    trusting_period: Duration,
-    last_channel_client_consensus_state_duration: Duration,
-) {
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap();
-
-    let expiry_time = last_channel_client_consensus_state_duration + trusting_period;
-    let min_time_before_client_expiration_str =
-        min_time_before_client_expiration.as_secs().to_string() + "s";
-
-    if expiry_time.gt(&now) {
-        let time_before_expire = (expiry_time - now).as_secs().try_into().unwrap();
-        ibc_client_time_before_expire_setter(
-            chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            &min_time_before_client_expiration_str,
-            time_before_expire,
-        );
-        if (expiry_time - now).gt(&min_time_before_client_expiration) {
-            ibc_client_status_setter(
-                chain_id,
+            ibc_channel_state_setter(
+                &chain_id,
                 port_id,
                 channel_id,
                 destination_chain_id,
-                &min_time_before_client_expiration_str,
-                0,
+                channel.state.into(),
             );
-        } else {
-            ibc_client_status_setter(
-                chain_id,
-                port_id,
-                channel_id,
-                destination_chain_id,
-                &min_time_before_client_expiration_str,
-                1,
+            alert::update(
+                Alert::channel("ibc_channel_state", &chain_id, &chain_channel)
+                    .value(format!("channel is {}", channel_state)),
+                !channel_open,
             );
-        }
-    } else {
-        ibc_client_time_before_expire_setter(
-            chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            &min_time_before_client_expiration_str,
-            0,
-        );
-        ibc_client_status_setter(
-            chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            &min_time_before_client_expiration_str,
-            1,
-        );
-    }
-}
-
-pub async fn track_ibc_status(
-    grpc_addr: Url,
-    chain_id: String,
-    chain_channel: config::Channel,
-    halt: Arc<tokio::sync::Mutex<bool>>,
-) {
-    let port_id = &chain_channel.port_id;
-    let channel_id = &chain_channel.channel_id;
-    let destination_chain_id = &chain_channel.destination_chain_id;
-    let refresh = &chain_channel.refresh;
-    let min_total = &chain_channel.min_total;
-    let mut total: u64;
-    let mut collect_interval = tokio::time::interval(refresh.to_owned());
 
-    loop {
-        collect_interval.tick().await;
-        if *halt.lock().await {
-            // remove the metrics to avoid false alarms
-            ibc_count_remover(
-                &chain_id,
-                port_id,
-                channel_id,
-                destination_chain_id,
-                min_total,
-            );
-            continue;
-        }
-        total = match query::get_packet_commitments_total(
-            port_id.into(),
-            channel_id.into(),
-            grpc_addr.to_string(),
-        )
-        .await
-        {
-            Ok(total) => {
-                ibc_query_status_setter(
-                    &chain_id,
-                    port_id,
-                    channel_id,
-                    destination_chain_id,
-                    &grpc_addr.to_string(),
-                    0,
-                );
-                total
-            }
-            Err(e) => {
-                error!("{} and retry next refresh", e);
-                ibc_query_status_setter(
-                    &chain_id,
-                    port_id,
-                    channel_id,
-                    destination_chain_id,
-                    &grpc_addr.to_string(),
-                    1,
+            let Some(connection_id) = channel.connection_hops.first() else {
+                return;
+            };
+            let connection = match client.get_connection(connection_id.into()).await.result {
+                Ok(connection) => connection,
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    return;
+                }
+            };
+            let connection_state = ConnectionState::try_from(connection.state)
+                .map(|x| x.as_str_name())
+                .unwrap_or("UNKNOWN");
+            let connection_open = connection.state == ConnectionState::Open as i32;
+            if !connection_open {
+                warn!(
+                    "The connection_id ({}) of channel_id ({}) on ({}) is {}",
+                    connection_id, channel_id, chain_id, connection_state
                 );
-                continue;
             }
-        };
-        info!(
-            "The latest total={} with channel_id ({}) with destination_chain_id {} on ({})",
-            total, channel_id, destination_chain_id, chain_id
-        );
-        if total < min_total.parse::<u64>().unwrap() {
-            ibc_status_setter(
+            ibc_connection_state_setter(
                 &chain_id,
                 port_id,
                 channel_id,
                 destination_chain_id,
-                min_total,
-                0,
+                connection_id,
+                connection.state.into(),
             );
-        } else {
-            warn!("The current total {} with channel_id ({}) is higher than {} with destination_chain_id {} on ({})", total, channel_id, min_total, destination_chain_id, chain_id);
-            ibc_status_setter(
-                &chain_id,
-                port_id,
-                channel_id,
-                destination_chain_id,
-                min_total,
-                1,
+            alert::update(
+                Alert::channel("ibc_connection_state", &chain_id, &chain_channel).value(format!(
+                    "connection {} is {}",
+                    connection_id, connection_state
+                )),
+                !connection_open,
             );
         }
-        ibc_count_setter(
-            &chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            min_total,
-            total.try_into().unwrap(),
-        );
+        .instrument(cycle)
+        .await;
     }
 }
 
 pub async fn track_query_node_sync_status(
-    grpc_addr: Url,
+    client: query::ChainClient,
     chain_id: String,
     halt: Arc<tokio::sync::Mutex<bool>>,
 ) {
-    let refresh = Duration::from_secs(60);
-    let mut collect_interval = tokio::time::interval(refresh.to_owned());
+    let mut collect_interval = tokio::time::interval(NODE_SYNC_REFRESH);
     let mut last_height = 0;
 
     loop {
         collect_interval.tick().await;
 
-        let current_height = match query::get_latest_height(grpc_addr.to_string()).await {
-            Ok(height) => height,
-            Err(e) => {
-                error!("{} and retry next refresh", e);
-                continue;
-            }
-        };
+        let cycle = cycle_span(TaskLabels::chain("node_sync_status", &chain_id));
+        async {
+            let queried = client.get_latest_height().await;
+            let current_height = match queried.result {
+                Ok(height) => height,
+                Err(e) => {
+                    error!("{} and retry next refresh", e);
+                    status::update_chain(&chain_id, |x| {
+                        x.grpc_endpoint = Some(queried.endpoint.clone());
+                        x.failed(&e);
+                    });
+                    return;
+                }
+            };
 
-        if current_height > last_height {
-            last_height = current_height;
-            // set the halt to false
-            *halt.lock().await = false;
-            ibc_query_node_sync_status_setter(&chain_id, 0);
-        } else {
-            // set the halt to true, it should stop other tasks to avoid unnecessary alerts coz the commitments are not increasing which could give false alarms
-            *halt.lock().await = true;
-            ibc_query_node_sync_status_setter(&chain_id, 1);
+            let synced = current_height > last_height;
+            if synced {
+                last_height = current_height;
+                // set the halt to false
+                *halt.lock().await = false;
+                ibc_query_node_sync_status_setter(&chain_id, 0);
+            } else {
+                // set the halt to true, it should stop other tasks to avoid unnecessary alerts coz the commitments are not increasing which could give false alarms
+                *halt.lock().await = true;
+                ibc_query_node_sync_status_setter(&chain_id, 1);
+            }
+            alert::update(
+                Alert::chain("ibc_query_node_sync_status", &chain_id)
+                    .value(format!("latest height {}", current_height)),
+                !synced,
+            );
+            status::update_chain(&chain_id, |x| {
+                x.grpc_endpoint = Some(queried.endpoint.clone());
+                x.latest_height = Some(current_height);
+                x.node_synced = Some(synced);
+                x.succeeded();
+            });
         }
+        .instrument(cycle)
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_refresh, PacketTimeouts, PendingPackets, TrackedChannel};
+    use crate::{config, query::PacketTimeout};
+    use ibc_relayer_types::Height;
+    use std::time::{Duration, SystemTime};
+    use tokio::sync::watch;
+
+    #[tokio::test]
+    async fn channel_trackers_follow_reloaded_config() {
+        let discovery: config::Discovery = toml::from_str(
+            r#"
+            min_total = "10"
+            refresh = "1ms"
+            "#,
+        )
+        .unwrap();
+        let chain_channel = discovery.channel("transfer", "channel-0", "chain-B");
+        let tracked_channel = TrackedChannel::new(&chain_channel).unwrap();
+        assert_eq!(tracked_channel.min_unrelayed_acks.get(), 10);
+        let (updates, channel_config) = watch::channel(tracked_channel.clone());
+        let mut interval = tokio::time::interval(chain_channel.refresh);
+        assert_eq!(
+            next_refresh(&mut interval, &channel_config).await,
+            tracked_channel
+        );
+
+        let mut reloaded = chain_channel.clone();
+        reloaded.min_total = "20".to_string();
+        reloaded.refresh = Duration::from_millis(2);
+        let mut reloaded = TrackedChannel::new(&reloaded).unwrap();
+        updates.send_replace(reloaded.clone());
+        let current = next_refresh(&mut interval, &channel_config).await;
+        assert_eq!(current.min_total.get(), 20);
+        assert_eq!(current, reloaded);
+        assert_eq!(interval.period(), Duration::from_millis(2));
+
+        reloaded.config.min_total = "9223372036854775808".to_string();
+        assert!(TrackedChannel::new(&reloaded.config).is_err());
+
+        reloaded.config.min_total = "20".to_string();
+        reloaded.config.min_time_before_client_expiration = Some("1d".to_string());
+        assert_eq!(
+            TrackedChannel::new(&reloaded.config)
+                .unwrap()
+                .min_time_before_client_expiration,
+            Some(Duration::from_secs(86_400))
+        );
+        reloaded.config.min_time_before_client_expiration = Some("soon".to_string());
+        assert!(TrackedChannel::new(&reloaded.config).is_err());
+    }
+
+    #[test]
+    fn pending_packets_age_from_first_seen() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut pending_packets = PendingPackets::default();
+        assert_eq!(pending_packets.oldest_age(start), Duration::ZERO);
+
+        pending_packets.update(&[1, 2], start);
+        pending_packets.update(&[1, 2, 3], start + Duration::from_secs(60));
+        assert_eq!(
+            pending_packets.oldest_age(start + Duration::from_secs(90)),
+            Duration::from_secs(90)
+        );
+
+        // packets 1 and 2 got relayed
+        pending_packets.update(&[3], start + Duration::from_secs(120));
+        assert_eq!(
+            pending_packets.oldest_age(start + Duration::from_secs(120)),
+            Duration::from_secs(60)
+        );
+
+        pending_packets.update(&[], start + Duration::from_secs(180));
+        assert_eq!(
+            pending_packets.oldest_age(start + Duration::from_secs(180)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn packet_timeouts_near_and_passed() {
+        let height = |h| Height::new(1, h).unwrap();
+        let secs = Duration::from_secs;
+        let mut packet_timeouts = PacketTimeouts::default();
+        // by timestamp
+        packet_timeouts.insert(
+            1,
+            PacketTimeout {
+                height: None,
+                timestamp: Some(secs(1_300)),
+            },
+        );
+        // by height, 6s blocks
+        packet_timeouts.insert(
+            2,
+            PacketTimeout {
+                height: Some(height(150)),
+                timestamp: None,
+            },
+        );
+        // far away
+        packet_timeouts.insert(
+            3,
+            PacketTimeout {
+                height: Some(height(100_000)),
+                timestamp: Some(secs(100_000)),
+            },
+        );
+
+        // block time unknown yet, only the timestamp counts
+        assert_eq!(
+            packet_timeouts.check(height(90), secs(1_000), secs(600)),
+            (1, 0)
+        );
+        assert_eq!(
+            packet_timeouts.check(height(100), secs(1_060), secs(600)),
+            (2, 0)
+        );
+        assert_eq!(
+            packet_timeouts.check(height(150), secs(1_360), secs(600)),
+            (0, 2)
+        );
+        // timed out packets are counted once
+        assert_eq!(
+            packet_timeouts.check(height(160), secs(1_420), secs(600)),
+            (0, 0)
+        );
+
+        // already timed out when first checked, e.g. after a restart
+        packet_timeouts.insert(
+            4,
+            PacketTimeout {
+                height: None,
+                timestamp: Some(secs(1_400)),
+            },
+        );
+        assert_eq!(
+            packet_timeouts.check(height(170), secs(1_480), secs(600)),
+            (0, 0)
+        );
+
+        // more than u32::MAX blocks away
+        packet_timeouts.insert(
+            5,
+            PacketTimeout {
+                height: Some(height(180 + (1 << 32))),
+                timestamp: None,
+            },
+        );
+        assert_eq!(
+            packet_timeouts.check(height(180), secs(1_540), secs(600)),
+            (0, 0)
+        );
+
+        packet_timeouts.retain(&[3]);
+        assert_eq!(packet_timeouts.unknown(&[3, 4], 10), vec![4]);
     }
 }
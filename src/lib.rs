@@ -10,10 +10,19 @@
 #[macro_use]
 extern crate more_asserts;
 
+pub mod alert;
+pub mod check;
 pub mod config;
+pub mod dashboard;
 pub mod error;
 pub mod handle;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod push;
 pub mod query;
+pub mod rules;
+pub mod status;
+pub mod supervisor;
 pub mod telemetry;
 
 pub const DEFAULT_CONFIG_PATH: &str = "chains.toml";
@@ -0,0 +1,177 @@
+//! Exports the metrics and a trace per polling cycle to an OTLP collector,
+//! for platforms collecting OpenTelemetry instead of scraping Prometheus
+use crate::{config::OtelConfig, error::Error, telemetry::metrics};
+use log::error;
+use opentelemetry::{
+    metrics::{Meter, MeterProvider as _},
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    runtime,
+    trace::TracerProvider,
+    Resource,
+};
+use prometheus::core::Collector;
+use prometheus::proto::{Metric, MetricType};
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, Layer};
+
+/// The providers started by `init`, flushed and stopped by `shutdown`
+pub struct Otel {
+    meter_provider: Option<SdkMeterProvider>,
+    tracer_provider: Option<TracerProvider>,
+}
+
+/// Starts the exporters enabled in the config. The metrics are observed from
+/// the Prometheus collectors at every export, so they keep their names and
+/// labels. The cycle and grpc spans of the trackers are exported through a
+/// tracing subscriber, which must not be installed already.
+pub fn init(config: &OtelConfig) -> Result<Otel, Error> {
+    let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+
+    let meter_provider = if config.metrics {
+        let exporter = opentelemetry_otlp::MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(config.endpoint.to_string())
+            .build()
+            .map_err(|e| Error::otlp_exporter(e.to_string()))?;
+        let reader = PeriodicReader::builder(exporter, runtime::Tokio)
+            .with_interval(config.interval)
+            .build();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource.clone())
+            .build();
+        let meter = provider.meter("ibc-watcher");
+        for collector in metrics().collectors() {
+            observe(&meter, collector);
+        }
+        Some(provider)
+    } else {
+        None
+    };
+
+    let tracer_provider = if config.traces {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(config.endpoint.to_string())
+            .build()
+            .map_err(|e| Error::otlp_exporter(e.to_string()))?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(resource)
+            .build();
+        let layer = tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("ibc-watcher"))
+            .with_filter(Targets::new().with_target("cosmos_ibc_watcher", tracing::Level::INFO));
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+            .map_err(|e| Error::otlp_exporter(e.to_string()))?;
+        Some(provider)
+    } else {
+        None
+    };
+
+    Ok(Otel {
+        meter_provider,
+        tracer_provider,
+    })
+}
+
+impl Otel {
+    /// Exports what is still buffered and stops the exporters
+    pub fn shutdown(self) {
+        if let Some(provider) = self.meter_provider {
+            if let Err(e) = provider.shutdown() {
+                error!("cannot shut down the OTLP metrics exporter: {}", e);
+            }
+        }
+        if let Some(provider) = self.tracer_provider {
+            if let Err(e) = provider.shutdown() {
+                error!("cannot shut down the OTLP traces exporter: {}", e);
+            }
+        }
+    }
+}
+
+/// Registers an observable instrument per metric of the collector. Gauges
+/// and counters are mirrored, histograms are left out as the grpc spans
+/// carry the latency of every call.
+fn observe(meter: &Meter, collector: Box<dyn Collector>) {
+    let families = collector.collect();
+    let Some(family) = families.first() else {
+        return;
+    };
+    let name = family.get_name().to_string();
+    let help = family.get_help().to_string();
+    match family.get_field_type() {
+        MetricType::GAUGE => {
+            meter
+                .i64_observable_gauge(name)
+                .with_description(help)
+                .with_callback(move |observer| {
+                    for family in collector.collect() {
+                        for metric in family.get_metric() {
+                            observer.observe(
+                                metric.get_gauge().get_value() as i64,
+                                &attributes(metric),
+                            );
+                        }
+                    }
+                })
+                .build();
+        }
+        MetricType::COUNTER => {
+            meter
+                .u64_observable_counter(name)
+                .with_description(help)
+                .with_callback(move |observer| {
+                    for family in collector.collect() {
+                        for metric in family.get_metric() {
+                            observer.observe(
+                                metric.get_counter().get_value() as u64,
+                                &attributes(metric),
+                            );
+                        }
+                    }
+                })
+                .build();
+        }
+        _ => {}
+    }
+}
+
+/// The labels of a sample as attributes, the constant labels included
+fn attributes(metric: &Metric) -> Vec<KeyValue> {
+    metric
+        .get_label()
+        .iter()
+        .map(|x| KeyValue::new(x.get_name().to_string(), x.get_value().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::PrometheusConfig, telemetry::Metrics};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn attributes_carry_constant_labels() {
+        let metrics = Metrics::new(&PrometheusConfig {
+            const_labels: BTreeMap::from([("env".to_string(), "test".to_string())]),
+            ..Default::default()
+        });
+        metrics
+            .ibc_watcher_task_restarts
+            .with_label_values(&["ibc_status", "chain-A", "transfer", "channel-0"])
+            .inc();
+
+        let families = metrics.ibc_watcher_task_restarts.collect();
+        let attributes = attributes(&families[0].get_metric()[0]);
+        assert!(attributes.contains(&KeyValue::new("env", "test")));
+        assert!(attributes.contains(&KeyValue::new("chain_id", "chain-A")));
+        assert_eq!(attributes.len(), 5);
+    }
+}
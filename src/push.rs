@@ -0,0 +1,415 @@
+//! Pushes the metrics to a Prometheus Pushgateway or a remote-write endpoint,
+//! for watchers running where Prometheus cannot scrape them
+use crate::{
+    config::{PushConfig, PushKind},
+    error::summary,
+    telemetry::metrics,
+};
+use log::{error, info};
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::{Encoder, TextEncoder};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the endpoint may take to accept a push
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The messages of the remote-write protocol, `prometheus.WriteRequest`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeSeries {
+    /// sorted by name, `__name__` included
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    /// milliseconds since the epoch
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+/// Pushes the metrics every interval until `shutdown` completes. The group
+/// of the Pushgateway is deleted then, so the metrics of a stopped watcher
+/// do not linger as if it was still running.
+pub async fn run(config: PushConfig, shutdown: impl Future<Output = ()>) {
+    let client = reqwest::Client::builder()
+        .timeout(PUSH_TIMEOUT)
+        .build()
+        .expect("http client can be built");
+    let mut push_interval = tokio::time::interval(config.interval);
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = push_interval.tick() => {
+                if let Err(e) = push(&client, &config, &metrics().registry.gather()).await {
+                    error!("cannot push metrics to {}: {}", config.url, summary(&e));
+                }
+            }
+        }
+    }
+    if config.kind == PushKind::Pushgateway {
+        match delete(&client, &config).await {
+            Ok(()) => info!(
+                "deleted the metrics of job {} on {}",
+                config.job, config.url
+            ),
+            Err(e) => error!("cannot delete metrics on {}: {}", config.url, summary(&e)),
+        }
+    }
+}
+
+/// Sends the metric families to the endpoint of the config
+pub async fn push(
+    client: &reqwest::Client,
+    config: &PushConfig,
+    families: &[MetricFamily],
+) -> Result<(), reqwest::Error> {
+    let request = match config.kind {
+        PushKind::Pushgateway => {
+            let encoder = TextEncoder::new();
+            let mut body = Vec::new();
+            encoder
+                .encode(families, &mut body)
+                .expect("metrics can be encoded");
+            client
+                .put(group_url(config))
+                .header(reqwest::header::CONTENT_TYPE, encoder.format_type())
+                .body(body)
+        }
+        PushKind::RemoteWrite => {
+            let body =
+                prost::Message::encode_to_vec(&write_request(config, families, now_millis()));
+            client
+                .post(config.url.to_string())
+                .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+                .header(reqwest::header::CONTENT_ENCODING, "snappy")
+                .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                .body(
+                    snap::raw::Encoder::new()
+                        .compress_vec(&body)
+                        .expect("request can be compressed"),
+                )
+        }
+    };
+    authorize(request, config)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Deletes the group of the job on the Pushgateway
+async fn delete(client: &reqwest::Client, config: &PushConfig) -> Result<(), reqwest::Error> {
+    authorize(client.delete(group_url(config)), config)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn authorize(request: reqwest::RequestBuilder, config: &PushConfig) -> reqwest::RequestBuilder {
+    match &config.username {
+        Some(username) => request.basic_auth(username, config.password.as_ref()),
+        None => request,
+    }
+}
+
+/// `{url}/metrics/job/{job}/instance/{instance}`
+fn group_url(config: &PushConfig) -> reqwest::Url {
+    let mut url: reqwest::Url = config
+        .url
+        .to_string()
+        .parse()
+        .expect("url of the config is valid");
+    url.path_segments_mut()
+        .expect("http url has a path")
+        .pop_if_empty()
+        .extend(["metrics", "job", &config.job, "instance", &config.instance]);
+    url
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// A series per gauge and counter, and the `_bucket`, `_sum` and `_count`
+/// series of every histogram, all sampled at `timestamp`. Every series gets
+/// the `job` and `instance` labels, as the group of the Pushgateway would.
+fn write_request(config: &PushConfig, families: &[MetricFamily], timestamp: i64) -> WriteRequest {
+    let mut timeseries = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let mut series = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                let mut labels: Vec<Label> = metric
+                    .get_label()
+                    .iter()
+                    .map(|x| Label {
+                        name: x.get_name().to_string(),
+                        value: x.get_value().to_string(),
+                    })
+                    .chain(extra.map(|(name, value)| Label {
+                        name: name.to_string(),
+                        value,
+                    }))
+                    .chain([
+                        Label {
+                            name: "__name__".to_string(),
+                            value: format!("{}{}", name, suffix),
+                        },
+                        Label {
+                            name: "job".to_string(),
+                            value: config.job.clone(),
+                        },
+                        Label {
+                            name: "instance".to_string(),
+                            value: config.instance.clone(),
+                        },
+                    ])
+                    .collect();
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+                timeseries.push(TimeSeries {
+                    labels,
+                    samples: vec![Sample { value, timestamp }],
+                });
+            };
+            match family.get_field_type() {
+                MetricType::GAUGE => series("", None, metric.get_gauge().get_value()),
+                MetricType::COUNTER => series("", None, metric.get_counter().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        series(
+                            "_bucket",
+                            Some(("le", bucket.get_upper_bound().to_string())),
+                            bucket.get_cumulative_count() as f64,
+                        );
+                    }
+                    series(
+                        "_bucket",
+                        Some(("le", "+Inf".to_string())),
+                        histogram.get_sample_count() as f64,
+                    );
+                    series("_sum", None, histogram.get_sample_sum());
+                    series("_count", None, histogram.get_sample_count() as f64);
+                }
+                // the watcher has no summaries or untyped metrics
+                _ => {}
+            }
+        }
+    }
+    WriteRequest { timeseries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PrometheusConfig;
+    use crate::telemetry::Metrics;
+    use prost::Message;
+    use tokio::sync::mpsc;
+    use warp::Filter;
+
+    #[derive(Debug)]
+    struct Received {
+        method: String,
+        path: String,
+        headers: warp::http::HeaderMap,
+        body: warp::hyper::body::Bytes,
+    }
+
+    /// An endpoint on a free local port, forwarding every request it gets
+    fn push_stand_in() -> (tendermint_rpc::Url, mpsc::UnboundedReceiver<Received>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let route = warp::method()
+            .and(warp::path::full())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(
+                move |method: warp::http::Method, path: warp::path::FullPath, headers, body| {
+                    sender
+                        .send(Received {
+                            method: method.to_string(),
+                            path: path.as_str().to_string(),
+                            headers,
+                            body,
+                        })
+                        .unwrap();
+                    warp::reply()
+                },
+            );
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::task::spawn(server);
+        (format!("http://{}/push", addr).parse().unwrap(), receiver)
+    }
+
+    async fn received(receiver: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("endpoint was called")
+            .unwrap()
+    }
+
+    fn push_config(kind: PushKind, url: tendermint_rpc::Url) -> PushConfig {
+        PushConfig {
+            kind,
+            url,
+            interval: Duration::from_secs(30),
+            job: "ibc-watcher".to_string(),
+            instance: "watcher-0".to_string(),
+            username: Some("watcher".to_string()),
+            password: Some("secret".to_string()),
+        }
+    }
+
+    fn recorded_metrics() -> Metrics {
+        let metrics = Metrics::new(&PrometheusConfig {
+            const_labels: [("environment".to_string(), "testnet".to_string())].into(),
+            ..Default::default()
+        });
+        metrics.set_gauge(
+            &metrics.ibc_count,
+            &["chain_A", "transfer", "channel-0", "chain_B"],
+            3,
+        );
+        metrics
+            .ibc_query_duration
+            .with_label_values(&["chain_A", "http://127.0.0.1:9090", "GetLatestBlock"])
+            .observe(0.2);
+        metrics
+    }
+
+    #[tokio::test]
+    async fn push_to_pushgateway() {
+        let (url, mut receiver) = push_stand_in();
+        let config = push_config(PushKind::Pushgateway, url);
+        let client = reqwest::Client::new();
+
+        push(&client, &config, &recorded_metrics().registry.gather())
+            .await
+            .unwrap();
+        let request = received(&mut receiver).await;
+        assert_eq!(request.method, "PUT");
+        assert_eq!(
+            request.path,
+            "/push/metrics/job/ibc-watcher/instance/watcher-0"
+        );
+        // watcher:secret
+        assert_eq!(
+            request.headers["authorization"],
+            "Basic d2F0Y2hlcjpzZWNyZXQ="
+        );
+        let body = String::from_utf8(request.body.to_vec()).unwrap();
+        assert!(body.contains(
+            "ibc_count{chain_id=\"chain_A\",channel_id=\"channel-0\",destination_chain_id=\"chain_B\",environment=\"testnet\",port_id=\"transfer\"} 3"
+        ));
+
+        delete(&client, &config).await.unwrap();
+        let request = received(&mut receiver).await;
+        assert_eq!(request.method, "DELETE");
+        assert_eq!(
+            request.path,
+            "/push/metrics/job/ibc-watcher/instance/watcher-0"
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_write_snappy_protobuf() {
+        let (url, mut receiver) = push_stand_in();
+        let mut config = push_config(PushKind::RemoteWrite, url);
+        config.username = None;
+        config.password = None;
+
+        push(
+            &reqwest::Client::new(),
+            &config,
+            &recorded_metrics().registry.gather(),
+        )
+        .await
+        .unwrap();
+        let request = received(&mut receiver).await;
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/push");
+        assert_eq!(request.headers["content-encoding"], "snappy");
+        assert_eq!(request.headers["content-type"], "application/x-protobuf");
+        assert!(request.headers.get("authorization").is_none());
+
+        let body = snap::raw::Decoder::new()
+            .decompress_vec(&request.body)
+            .unwrap();
+        let write_request = WriteRequest::decode(body.as_slice()).unwrap();
+        let series = |name: &str, le: Option<&str>| {
+            write_request.timeseries.iter().find(|x| {
+                x.labels
+                    .iter()
+                    .any(|x| x.name == "__name__" && x.value == name)
+                    && le.is_none_or(|le| x.labels.iter().any(|x| x.name == "le" && x.value == le))
+            })
+        };
+        let count = series("ibc_count", None).expect("gauge is written");
+        let names: Vec<&str> = count.labels.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "__name__",
+                "chain_id",
+                "channel_id",
+                "destination_chain_id",
+                "environment",
+                "instance",
+                "job",
+                "port_id"
+            ]
+        );
+        assert!(count
+            .labels
+            .iter()
+            .any(|x| x.name == "instance" && x.value == "watcher-0"));
+        assert_eq!(count.samples[0].value, 3.0);
+        assert_eq!(
+            series("ibc_query_duration_seconds_bucket", Some("0.25"))
+                .unwrap()
+                .samples[0]
+                .value,
+            1.0
+        );
+        assert_eq!(
+            series("ibc_query_duration_seconds_bucket", Some("+Inf"))
+                .unwrap()
+                .samples[0]
+                .value,
+            1.0
+        );
+        assert_eq!(
+            series("ibc_query_duration_seconds_sum", None)
+                .unwrap()
+                .samples[0]
+                .value,
+            0.2
+        );
+    }
+}
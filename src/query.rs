@@ -1,155 +1,933 @@
 use anyhow::Result;
+use http::uri::PathAndQuery;
 use http::uri::Uri;
+use ibc_proto::cosmos::base::abci::v1beta1::TxResponse;
 use ibc_proto::cosmos::base::query::v1beta1::PageRequest;
 use ibc_proto::cosmos::base::tendermint::v1beta1::{
     service_client::ServiceClient, GetLatestBlockRequest,
 };
 use ibc_proto::ibc::core::channel::v1::{
-    query_client::QueryClient, QueryChannelClientStateRequest, QueryChannelConsensusStateRequest,
-    QueryPacketCommitmentsRequest,
+    query_client::QueryClient, Channel as ChannelEnd, IdentifiedChannel,
+    QueryChannelClientStateRequest, QueryChannelConsensusStateRequest, QueryChannelRequest,
+    QueryChannelsRequest, QueryPacketAcknowledgementsRequest, QueryPacketCommitmentsRequest,
+    QueryUnreceivedAcksRequest, QueryUnreceivedPacketsRequest,
+};
+use ibc_proto::ibc::core::client::v1::{
+    query_client::QueryClient as ClientQueryClient, QueryClientStatusRequest,
+};
+use ibc_proto::ibc::core::connection::v1::{
+    query_client::QueryClient as ConnectionQueryClient, ConnectionEnd,
+    QueryConnectionClientStateRequest, QueryConnectionRequest,
 };
 
+use crate::config;
+use crate::telemetry::{ibc_query_duration_observer, ibc_query_failures_incrementer};
 use ibc_relayer::client_state::IdentifiedAnyClientState;
 use ibc_relayer::consensus_state::AnyConsensusState;
 use ibc_relayer_types::Height;
-use std::time::Duration;
+use log::{info, warn};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+use tonic::{codec::ProstCodec, transport::Channel, Code, Request, Response, Status};
+use tracing::{field, Instrument};
 
-/// Fetches on-chain data of given port_id, channel_id and chain
-pub async fn get_packet_commitments_total(
-    port_id: String,
-    channel_id: String,
-    grpc_addr: String,
-) -> Result<u64> {
-    let mut query_client = create_grpc_client(grpc_addr.parse::<Uri>()?, QueryClient::new).await?;
-
-    let page_request = PageRequest {
-        key: vec![],
-        offset: 1,
-        limit: 100,
-        count_total: true,
-        reverse: true,
-    };
-    let request = QueryPacketCommitmentsRequest {
-        port_id,
-        channel_id,
-        pagination: Some(page_request),
-    };
-
-    Ok(query_client
-        .packet_commitments(request)
-        .await?
-        .into_inner()
-        .pagination
-        .map(|x| x.total)
-        .ok_or_else(crate::error::Error::get_packet_commitments_total)?)
-}
-
-/// Fetches trusting period of the channel
-pub async fn get_trusting_period(
-    port_id: String,
-    channel_id: String,
-    grpc_addr: String,
-) -> Result<Duration> {
-    let mut query_client = create_grpc_client(grpc_addr.parse::<Uri>()?, QueryClient::new).await?;
-
-    let request = QueryChannelClientStateRequest {
-        port_id,
-        channel_id,
-    };
-
-    Ok(query_client
-        .channel_client_state(request)
-        .await?
-        .into_inner()
-        .identified_client_state
-        .ok_or_else(crate::error::Error::get_channel_client_state)
-        .map(IdentifiedAnyClientState::try_from)??
-        .client_state
-        .trusting_period())
-}
-
-/// Fetch the latest client state height of the channel
-pub async fn get_latest_channel_client_state_height(
-    port_id: String,
-    channel_id: String,
-    grpc_addr: String,
-) -> Result<Height> {
-    let mut query_client = create_grpc_client(grpc_addr.parse::<Uri>()?, QueryClient::new).await?;
-
-    let request = QueryChannelClientStateRequest {
-        port_id,
-        channel_id,
-    };
-
-    Ok(query_client
-        .channel_client_state(request)
-        .await?
-        .into_inner()
-        .identified_client_state
-        .ok_or_else(crate::error::Error::get_channel_client_state)
-        .map(IdentifiedAnyClientState::try_from)??
-        .client_state
-        .latest_height())
-}
-
-/// Fetch the duration of the latest ibc client consensus state by height
-pub async fn get_latest_channel_client_consensus_state_duration(
-    port_id: String,
-    channel_id: String,
-    height: Height,
+/// Page size of paginated queries
+const PAGE_LIMIT: u64 = 1000;
+
+/// How long a failing endpoint is ranked behind healthy ones before it is
+/// probed again.
+const ENDPOINT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// `cosmos.tx.v1beta1.GetTxsEventRequest` including the `query` field of
+/// Cosmos SDK 0.50, which ibc-proto does not ship yet. Older nodes read
+/// `events`, newer ones `query`.
+#[derive(Clone, PartialEq, prost::Message)]
+struct GetTxsEventRequest {
+    #[prost(string, repeated, tag = "1")]
+    events: Vec<String>,
+    #[prost(uint64, tag = "4")]
+    page: u64,
+    #[prost(uint64, tag = "5")]
+    limit: u64,
+    #[prost(string, tag = "6")]
+    query: String,
+}
+
+/// The subset of `cosmos.tx.v1beta1.GetTxsEventResponse` the watcher reads
+#[derive(Clone, PartialEq, prost::Message)]
+struct GetTxsEventResponse {
+    #[prost(message, repeated, tag = "2")]
+    tx_responses: Vec<TxResponse>,
+}
+
+/// Timeout of a packet as set when it was sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketTimeout {
+    /// None if the packet has no timeout height
+    pub height: Option<Height>,
+    /// Since the unix epoch, none if the packet has no timeout timestamp
+    pub timestamp: Option<Duration>,
+}
+
+/// Status of a light client as reported by the 02-client `ClientStatus` query
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientStatus {
+    Active = 0,
+    Expired = 1,
+    Frozen = 2,
+    Unauthorized = 3,
+    Unknown = 4,
+}
+
+impl From<&str> for ClientStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "Active" => Self::Active,
+            "Expired" => Self::Expired,
+            "Frozen" => Self::Frozen,
+            "Unauthorized" => Self::Unauthorized,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A gRPC client of a single chain.
+///
+/// The client holds one or more endpoints of the chain. The transport channel
+/// of each endpoint is created on first use and shared by all clones, so the
+/// tasks polling the same chain reuse their connections instead of doing a TLS
+/// handshake per query. Queries are spread round-robin over the healthiest
+/// endpoints and fail over to the next healthiest one when an endpoint cannot
+/// be reached.
+#[derive(Clone, Debug)]
+pub struct ChainClient {
+    /// labels the query metrics
+    chain_id: String,
+    endpoints: Arc<Vec<Endpoint>>,
+    /// the endpoint to start the next round-robin order at
+    next: Arc<AtomicUsize>,
+}
+
+/// The outcome of a query and the endpoint which answered it, or which
+/// failed last
+#[derive(Debug)]
+pub struct Queried<T> {
+    pub endpoint: String,
+    pub result: Result<T>,
+}
+
+impl<T> Queried<T> {
+    /// Continues with the answer, keeping the endpoint
+    pub fn and_then<U>(self, f: impl FnOnce(T) -> Result<U>) -> Queried<U> {
+        Queried {
+            endpoint: self.endpoint,
+            result: self.result.and_then(f),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
     grpc_addr: String,
-) -> Result<Duration> {
-    let mut query_client = create_grpc_client(grpc_addr.parse::<Uri>()?, QueryClient::new).await?;
-
-    let request = QueryChannelConsensusStateRequest {
-        port_id,
-        channel_id,
-        revision_height: height.revision_height(),
-        revision_number: height.revision_number(),
-    };
-
-    Ok(Duration::from_nanos(
-        query_client
-            .channel_consensus_state(request)
-            .await?
-            .into_inner()
-            .consensus_state
-            .ok_or_else(crate::error::Error::get_channel_consensus_state)
-            .map(AnyConsensusState::try_from)??
-            .timestamp()
-            .nanoseconds(),
-    ))
-}
-
-/// fetches the latest block height of the chain
-pub async fn get_latest_height(grpc_addr: String) -> Result<i64> {
-    let mut query_client =
-        create_grpc_client(grpc_addr.parse::<Uri>()?, ServiceClient::new).await?;
-
-    Ok(query_client
-        .get_latest_block(GetLatestBlockRequest {})
-        .await?
-        .into_inner()
-        .block
-        .ok_or_else(crate::error::Error::get_latest_block)?
-        .header
-        .ok_or_else(crate::error::Error::get_latest_block)?
-        .height)
-}
-
-/// Helper function to create a gRPC client.
-pub async fn create_grpc_client<T>(
+    uri: Uri,
+    connect_timeout: Duration,
+    timeout: Duration,
+    channel: std::sync::Mutex<Option<Channel>>,
+    health: std::sync::Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// consecutive failed calls
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl Health {
+    /// Lower is healthier. Failures older than the cooldown are forgiven so the
+    /// endpoint gets probed again.
+    fn score(&self) -> u32 {
+        match self.last_failure {
+            Some(at) if at.elapsed() < ENDPOINT_COOLDOWN => self.failures,
+            _ => 0,
+        }
+    }
+}
+
+impl ChainClient {
+    /// A client with the default timeouts
+    pub fn new(chain_id: &str, grpc_addrs: &[String]) -> Result<Self> {
+        Self::with_timeouts(
+            chain_id,
+            grpc_addrs,
+            config::default::connect_timeout(),
+            config::default::timeout(),
+        )
+    }
+
+    /// A client of all endpoints of the chain, with its timeouts
+    pub fn from_config(chain_config: &config::ChainConfig) -> Result<Self> {
+        Self::with_timeouts(
+            &chain_config.id,
+            &chain_config.grpc_addrs(),
+            chain_config.connect_timeout,
+            chain_config.timeout,
+        )
+    }
+
+    /// A client giving up on connecting to an endpoint after `connect_timeout`
+    /// and on a query after `timeout`, so an unresponsive endpoint cannot
+    /// stall the trackers
+    pub fn with_timeouts(
+        chain_id: &str,
+        grpc_addrs: &[String],
+        connect_timeout: Duration,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let endpoints = grpc_addrs
+            .iter()
+            .map(|grpc_addr| {
+                Ok(Endpoint {
+                    grpc_addr: grpc_addr.clone(),
+                    uri: grpc_addr.parse::<Uri>()?,
+                    connect_timeout,
+                    timeout,
+                    channel: Default::default(),
+                    health: Default::default(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if endpoints.is_empty() {
+            return Err(crate::error::Error::no_grpc_endpoint().into());
+        }
+        Ok(Self {
+            chain_id: chain_id.to_string(),
+            endpoints: Arc::new(endpoints),
+            next: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Fetches on-chain data of given port_id, channel_id and chain
+    pub async fn get_packet_commitments_total(
+        &self,
+        port_id: String,
+        channel_id: String,
+    ) -> Queried<u64> {
+        let page_request = PageRequest {
+            key: vec![],
+            offset: 1,
+            limit: 100,
+            count_total: true,
+            reverse: true,
+        };
+        let request = QueryPacketCommitmentsRequest {
+            port_id,
+            channel_id,
+            pagination: Some(page_request),
+        };
+
+        self.call("ibc.core.channel.v1.Query/PacketCommitments", |channel| {
+            let request = request.clone();
+            async move { QueryClient::new(channel).packet_commitments(request).await }
+        })
+        .await
+        .and_then(|response| {
+            Ok(response
+                .pagination
+                .map(|x| x.total)
+                .ok_or_else(crate::error::Error::get_packet_commitments_total)?)
+        })
+    }
+
+    /// Fetches the sequences of all outstanding packet commitments of the channel
+    pub async fn get_packet_commitment_sequences(
+        &self,
+        port_id: String,
+        channel_id: String,
+    ) -> Queried<Vec<u64>> {
+        let mut sequences = Vec::new();
+        let mut next_key = vec![];
+
+        loop {
+            let request = QueryPacketCommitmentsRequest {
+                port_id: port_id.clone(),
+                channel_id: channel_id.clone(),
+                pagination: Some(PageRequest {
+                    key: next_key,
+                    offset: 0,
+                    limit: PAGE_LIMIT,
+                    count_total: false,
+                    reverse: false,
+                }),
+            };
+            let Queried { endpoint, result } = self
+                .call("ibc.core.channel.v1.Query/PacketCommitments", |channel| {
+                    let request = request.clone();
+                    async move { QueryClient::new(channel).packet_commitments(request).await }
+                })
+                .await;
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    return Queried {
+                        endpoint,
+                        result: Err(e),
+                    }
+                }
+            };
+
+            sequences.extend(response.commitments.iter().map(|x| x.sequence));
+            next_key = response.pagination.map(|x| x.next_key).unwrap_or_default();
+            if next_key.is_empty() {
+                return Queried {
+                    endpoint,
+                    result: Ok(sequences),
+                };
+            }
+        }
+    }
+
+    /// Filters the given packet commitment sequences of the source chain down
+    /// to the ones not yet received on this chain. `port_id` and `channel_id`
+    /// are the counterparty end on this chain.
+    pub async fn get_unreceived_packets(
+        &self,
+        port_id: String,
+        channel_id: String,
+        packet_commitment_sequences: Vec<u64>,
+    ) -> Queried<Vec<u64>> {
+        let request = QueryUnreceivedPacketsRequest {
+            port_id,
+            channel_id,
+            packet_commitment_sequences,
+        };
+
+        self.call("ibc.core.channel.v1.Query/UnreceivedPackets", |channel| {
+            let request = request.clone();
+            async move { QueryClient::new(channel).unreceived_packets(request).await }
+        })
+        .await
+        .and_then(|response| Ok(response.sequences))
+    }
+
+    /// Fetches the sequences of the packet acknowledgements written on this
+    /// chain, restricted to the given packet commitment sequences of the
+    /// counterparty. `port_id` and `channel_id` are the end on this chain.
+    pub async fn get_packet_acknowledgement_sequences(
+        &self,
+        port_id: String,
+        channel_id: String,
+        packet_commitment_sequences: Vec<u64>,
+    ) -> Queried<Vec<u64>> {
+        let mut sequences = Vec::new();
+        let mut next_key = vec![];
+
+        loop {
+            let request = QueryPacketAcknowledgementsRequest {
+                port_id: port_id.clone(),
+                channel_id: channel_id.clone(),
+                pagination: Some(PageRequest {
+                    key: next_key,
+                    offset: 0,
+                    limit: PAGE_LIMIT,
+                    count_total: false,
+                    reverse: false,
+                }),
+                packet_commitment_sequences: packet_commitment_sequences.clone(),
+            };
+            let Queried { endpoint, result } = self
+                .call(
+                    "ibc.core.channel.v1.Query/PacketAcknowledgements",
+                    |channel| {
+                        let request = request.clone();
+                        async move {
+                            QueryClient::new(channel)
+                                .packet_acknowledgements(request)
+                                .await
+                        }
+                    },
+                )
+                .await;
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    return Queried {
+                        endpoint,
+                        result: Err(e),
+                    }
+                }
+            };
+
+            sequences.extend(response.acknowledgements.iter().map(|x| x.sequence));
+            next_key = response.pagination.map(|x| x.next_key).unwrap_or_default();
+            if next_key.is_empty() {
+                return Queried {
+                    endpoint,
+                    result: Ok(sequences),
+                };
+            }
+        }
+    }
+
+    /// Filters the given acknowledgement sequences of the counterparty down to
+    /// the ones not yet relayed back to this chain
+    pub async fn get_unreceived_acks(
+        &self,
+        port_id: String,
+        channel_id: String,
+        packet_ack_sequences: Vec<u64>,
+    ) -> Queried<Vec<u64>> {
+        let request = QueryUnreceivedAcksRequest {
+            port_id,
+            channel_id,
+            packet_ack_sequences,
+        };
+
+        self.call("ibc.core.channel.v1.Query/UnreceivedAcks", |channel| {
+            let request = request.clone();
+            async move { QueryClient::new(channel).unreceived_acks(request).await }
+        })
+        .await
+        .and_then(|response| Ok(response.sequences))
+    }
+
+    /// Fetches the timeout of a sent packet from its `send_packet` event
+    pub async fn get_packet_timeout(
+        &self,
+        port_id: String,
+        channel_id: String,
+        sequence: u64,
+    ) -> Queried<PacketTimeout> {
+        let events = vec![
+            format!("send_packet.packet_src_port='{port_id}'"),
+            format!("send_packet.packet_src_channel='{channel_id}'"),
+            format!("send_packet.packet_sequence='{sequence}'"),
+        ];
+        let request = GetTxsEventRequest {
+            query: events.join(" AND "),
+            events,
+            page: 1,
+            limit: 1,
+        };
+
+        self.call("cosmos.tx.v1beta1.Service/GetTxsEvent", |channel| {
+            let request = request.clone();
+            async move {
+                let mut grpc = tonic::client::Grpc::new(channel);
+                grpc.ready()
+                    .await
+                    .map_err(|e| Status::unknown(format!("service was not ready: {e}")))?;
+                grpc.unary(
+                    Request::new(request),
+                    PathAndQuery::from_static("/cosmos.tx.v1beta1.Service/GetTxsEvent"),
+                    ProstCodec::<GetTxsEventRequest, GetTxsEventResponse>::default(),
+                )
+                .await
+            }
+        })
+        .await
+        .and_then(|response| {
+            response
+                .tx_responses
+                .iter()
+                .find_map(|tx| packet_timeout_from_tx(tx, &port_id, &channel_id, sequence))
+                .ok_or_else(|| crate::error::Error::get_send_packet(sequence).into())
+        })
+    }
+
+    /// Fetches the channel end
+    pub async fn get_channel(&self, port_id: String, channel_id: String) -> Queried<ChannelEnd> {
+        let request = QueryChannelRequest {
+            port_id,
+            channel_id,
+        };
+
+        self.call("ibc.core.channel.v1.Query/Channel", |channel| {
+            let request = request.clone();
+            async move { QueryClient::new(channel).channel(request).await }
+        })
+        .await
+        .and_then(|response| {
+            Ok(response
+                .channel
+                .ok_or_else(crate::error::Error::get_channel)?)
+        })
+    }
+
+    /// Fetches all channels of the chain
+    pub async fn get_channels(&self) -> Queried<Vec<IdentifiedChannel>> {
+        let mut channels = Vec::new();
+        let mut next_key = vec![];
+
+        loop {
+            let request = QueryChannelsRequest {
+                pagination: Some(PageRequest {
+                    key: next_key,
+                    offset: 0,
+                    limit: PAGE_LIMIT,
+                    count_total: false,
+                    reverse: false,
+                }),
+            };
+            let Queried { endpoint, result } = self
+                .call("ibc.core.channel.v1.Query/Channels", |channel| {
+                    let request = request.clone();
+                    async move { QueryClient::new(channel).channels(request).await }
+                })
+                .await;
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    return Queried {
+                        endpoint,
+                        result: Err(e),
+                    }
+                }
+            };
+
+            channels.extend(response.channels);
+            next_key = response.pagination.map(|x| x.next_key).unwrap_or_default();
+            if next_key.is_empty() {
+                return Queried {
+                    endpoint,
+                    result: Ok(channels),
+                };
+            }
+        }
+    }
+
+    /// Fetches the chain id of the counterparty chain of the connection, as
+    /// tracked by the connection's client
+    pub async fn get_connection_counterparty_chain_id(
+        &self,
+        connection_id: String,
+    ) -> Queried<String> {
+        let request = QueryConnectionClientStateRequest { connection_id };
+
+        self.call(
+            "ibc.core.connection.v1.Query/ConnectionClientState",
+            |channel| {
+                let request = request.clone();
+                async move {
+                    ConnectionQueryClient::new(channel)
+                        .connection_client_state(request)
+                        .await
+                }
+            },
+        )
+        .await
+        .and_then(|response| {
+            Ok(response
+                .identified_client_state
+                .ok_or_else(crate::error::Error::get_channel_client_state)
+                .map(IdentifiedAnyClientState::try_from)??
+                .client_state
+                .chain_id()
+                .to_string())
+        })
+    }
+
+    /// Fetches the connection end
+    pub async fn get_connection(&self, connection_id: String) -> Queried<ConnectionEnd> {
+        let request = QueryConnectionRequest { connection_id };
+
+        self.call("ibc.core.connection.v1.Query/Connection", |channel| {
+            let request = request.clone();
+            async move {
+                ConnectionQueryClient::new(channel)
+                    .connection(request)
+                    .await
+            }
+        })
+        .await
+        .and_then(|response| {
+            Ok(response
+                .connection
+                .ok_or_else(crate::error::Error::get_connection)?)
+        })
+    }
+
+    /// Fetches trusting period of the channel
+    pub async fn get_trusting_period(
+        &self,
+        port_id: String,
+        channel_id: String,
+    ) -> Queried<Duration> {
+        self.get_channel_client_state(port_id, channel_id)
+            .await
+            .and_then(|x| Ok(x.client_state.trusting_period()))
+    }
+
+    /// Fetch the latest client state height of the channel
+    pub async fn get_latest_channel_client_state_height(
+        &self,
+        port_id: String,
+        channel_id: String,
+    ) -> Queried<Height> {
+        self.get_channel_client_state(port_id, channel_id)
+            .await
+            .and_then(|x| Ok(x.client_state.latest_height()))
+    }
+
+    /// Fetch the client state of the channel
+    pub async fn get_channel_client_state(
+        &self,
+        port_id: String,
+        channel_id: String,
+    ) -> Queried<IdentifiedAnyClientState> {
+        let request = QueryChannelClientStateRequest {
+            port_id,
+            channel_id,
+        };
+
+        self.call("ibc.core.channel.v1.Query/ChannelClientState", |channel| {
+            let request = request.clone();
+            async move {
+                QueryClient::new(channel)
+                    .channel_client_state(request)
+                    .await
+            }
+        })
+        .await
+        .and_then(|response| {
+            Ok(response
+                .identified_client_state
+                .ok_or_else(crate::error::Error::get_channel_client_state)
+                .map(IdentifiedAnyClientState::try_from)??)
+        })
+    }
+
+    /// Fetch the status of the client as evaluated by the chain itself
+    pub async fn get_client_status(&self, client_id: String) -> Queried<ClientStatus> {
+        let request = QueryClientStatusRequest { client_id };
+
+        self.call("ibc.core.client.v1.Query/ClientStatus", |channel| {
+            let request = request.clone();
+            async move { ClientQueryClient::new(channel).client_status(request).await }
+        })
+        .await
+        .and_then(|response| Ok(response.status.as_str().into()))
+    }
+
+    /// Fetch the duration of the latest ibc client consensus state by height
+    pub async fn get_latest_channel_client_consensus_state_duration(
+        &self,
+        port_id: String,
+        channel_id: String,
+        height: Height,
+    ) -> Queried<Duration> {
+        let request = QueryChannelConsensusStateRequest {
+            port_id,
+            channel_id,
+            revision_height: height.revision_height(),
+            revision_number: height.revision_number(),
+        };
+
+        self.call(
+            "ibc.core.channel.v1.Query/ChannelConsensusState",
+            |channel| {
+                let request = request.clone();
+                async move {
+                    QueryClient::new(channel)
+                        .channel_consensus_state(request)
+                        .await
+                }
+            },
+        )
+        .await
+        .and_then(|response| {
+            Ok(Duration::from_nanos(
+                response
+                    .consensus_state
+                    .ok_or_else(crate::error::Error::get_channel_consensus_state)
+                    .map(AnyConsensusState::try_from)??
+                    .timestamp()
+                    .nanoseconds(),
+            ))
+        })
+    }
+
+    /// fetches the latest block height of the chain
+    pub async fn get_latest_height(&self) -> Queried<i64> {
+        self.get_latest_height_and_time()
+            .await
+            .and_then(|(height, _)| Ok(height))
+    }
+
+    /// fetches the latest block height and time of the chain, the time as
+    /// duration since the unix epoch
+    pub async fn get_latest_height_and_time(&self) -> Queried<(i64, Duration)> {
+        self.call(
+            "cosmos.base.tendermint.v1beta1.Service/GetLatestBlock",
+            |channel| async move {
+                ServiceClient::new(channel)
+                    .get_latest_block(GetLatestBlockRequest {})
+                    .await
+            },
+        )
+        .await
+        .and_then(|response| {
+            let header = response
+                .block
+                .ok_or_else(crate::error::Error::get_latest_block)?
+                .header
+                .ok_or_else(crate::error::Error::get_latest_block)?;
+            let time = header
+                .time
+                .ok_or_else(crate::error::Error::get_latest_block)?;
+            Ok((
+                header.height,
+                Duration::new(time.seconds.try_into()?, time.nanos.try_into()?),
+            ))
+        })
+    }
+
+    /// Runs a unary gRPC call against the endpoints of the chain.
+    ///
+    /// The endpoints are tried healthiest first, equally healthy ones in
+    /// round-robin order. When the connection to an endpoint is broken its
+    /// cached channel is dropped and the call moves on to the next endpoint; a
+    /// single endpoint is retried once on a fresh connection. Every attempt is timed and every failure counted
+    /// per endpoint under the given method name, and traced as a span of the
+    /// current polling cycle. The endpoint which answered, or which failed
+    /// last, is returned with the outcome.
+    async fn call<T, F, Fut>(&self, method: &'static str, f: F) -> Queried<T>
+    where
+        F: Fn(Channel) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let order = self.endpoint_order();
+        let attempts = order.len().max(2);
+        let mut last_error = None;
+        let mut last_endpoint = order[0];
+
+        for &index in order.iter().cycle().take(attempts) {
+            let endpoint = &self.endpoints[index];
+            last_endpoint = index;
+            // a child of the polling cycle the call is made in
+            let span = tracing::info_span!(
+                "grpc",
+                otel.name = method,
+                otel.kind = "client",
+                otel.status_code = field::Empty,
+                rpc.system = "grpc",
+                rpc.grpc.status_code = field::Empty,
+                chain_id = %self.chain_id,
+                endpoint = %endpoint.grpc_addr,
+            );
+
+            let channel = match endpoint.channel().instrument(span.clone()).await {
+                Ok(channel) => channel,
+                Err(e) => {
+                    span.record("otel.status_code", "ERROR");
+                    span.record("rpc.grpc.status_code", Code::Unavailable as i32);
+                    warn!(
+                        "cannot connect to gRPC endpoint {}: {}",
+                        endpoint.grpc_addr, e
+                    );
+                    endpoint.record_failure();
+                    // the code tonic reports for transport errors
+                    ibc_query_failures_incrementer(
+                        &self.chain_id,
+                        &endpoint.grpc_addr,
+                        method,
+                        Code::Unavailable,
+                    );
+                    last_error = Some(e.into());
+                    continue;
+                }
+            };
+            let start = Instant::now();
+            let result = f(channel).instrument(span.clone()).await;
+            ibc_query_duration_observer(
+                &self.chain_id,
+                &endpoint.grpc_addr,
+                method,
+                start.elapsed(),
+            );
+            span.record(
+                "rpc.grpc.status_code",
+                result.as_ref().map_or_else(|x| x.code(), |_| Code::Ok) as i32,
+            );
+            if let Err(status) = &result {
+                span.record("otel.status_code", "ERROR");
+                ibc_query_failures_incrementer(
+                    &self.chain_id,
+                    &endpoint.grpc_addr,
+                    method,
+                    status.code(),
+                );
+            }
+            match result {
+                Ok(response) => {
+                    endpoint.record_success();
+                    if order[0] != index {
+                        info!(
+                            "gRPC query of ({}) failed over from {} to {}",
+                            self.chain_id, self.endpoints[order[0]].grpc_addr, endpoint.grpc_addr
+                        );
+                    }
+                    return Queried {
+                        endpoint: endpoint.grpc_addr.clone(),
+                        result: Ok(response.into_inner()),
+                    };
+                }
+                Err(status) if is_connection_error(&status) => {
+                    warn!(
+                        "gRPC connection to {} is broken ({}), trying next endpoint",
+                        endpoint.grpc_addr,
+                        status.message()
+                    );
+                    endpoint.channel.lock().unwrap().take();
+                    endpoint.record_failure();
+                    last_error = Some(status.into());
+                }
+                Err(status) => {
+                    return Queried {
+                        endpoint: endpoint.grpc_addr.clone(),
+                        result: Err(status.into()),
+                    }
+                }
+            }
+        }
+
+        Queried {
+            endpoint: self.endpoints[last_endpoint].grpc_addr.clone(),
+            result: Err(
+                last_error.unwrap_or_else(|| crate::error::Error::no_grpc_endpoint().into())
+            ),
+        }
+    }
+
+    /// Indices of the endpoints in the order they should be tried, rotated
+    /// by one on every call and then sorted healthiest first
+    fn endpoint_order(&self) -> Vec<usize> {
+        let len = self.endpoints.len();
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut order: Vec<usize> = (0..len).map(|i| (next + i) % len).collect();
+        // stable sort, so equally healthy endpoints keep their rotation
+        order.sort_by_key(|&i| self.endpoints[i].health.lock().unwrap().score());
+        order
+    }
+}
+
+impl Endpoint {
+    /// Returns the cached channel, connecting first if there is none. The
+    /// lock is not held while connecting, so a blackholed endpoint does not
+    /// hold up the calls waiting for the channel; when several calls connect
+    /// at once, the first channel is kept.
+    async fn channel(&self) -> Result<Channel, crate::error::Error> {
+        if let Some(channel) = self.channel.lock().unwrap().as_ref() {
+            return Ok(channel.clone());
+        }
+        let connected =
+            create_grpc_channel(self.uri.clone(), self.connect_timeout, self.timeout).await?;
+        Ok(self
+            .channel
+            .lock()
+            .unwrap()
+            .get_or_insert(connected)
+            .clone())
+    }
+
+    fn record_success(&self) {
+        *self.health.lock().unwrap() = Health::default();
+    }
+
+    fn record_failure(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.failures = health.failures.saturating_add(1);
+        health.last_failure = Some(Instant::now());
+    }
+}
+
+/// Finds the timeout of the given packet among the `send_packet` events of the
+/// transaction. Nodes before Cosmos SDK 0.46 only report events in the logs.
+fn packet_timeout_from_tx(
+    tx: &TxResponse,
+    port_id: &str,
+    channel_id: &str,
+    sequence: u64,
+) -> Option<PacketTimeout> {
+    let events = tx.events.iter().map(|event| {
+        (
+            event.r#type.as_str(),
+            event
+                .attributes
+                .iter()
+                .map(|x| {
+                    (
+                        String::from_utf8_lossy(&x.key).to_string(),
+                        String::from_utf8_lossy(&x.value).to_string(),
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+        )
+    });
+    let log_events = tx.logs.iter().flat_map(|log| &log.events).map(|event| {
+        (
+            event.r#type.as_str(),
+            event
+                .attributes
+                .iter()
+                .map(|x| (x.key.clone(), x.value.clone()))
+                .collect::<HashMap<_, _>>(),
+        )
+    });
+
+    events
+        .chain(log_events)
+        .filter(|(kind, _)| *kind == "send_packet")
+        .find_map(|(_, attributes)| {
+            let attribute = |key: &str| attributes.get(key).map(String::as_str);
+            if attribute("packet_src_port") != Some(port_id)
+                || attribute("packet_src_channel") != Some(channel_id)
+                || attribute("packet_sequence") != Some(&sequence.to_string())
+            {
+                return None;
+            }
+            Some(PacketTimeout {
+                height: attribute("packet_timeout_height").and_then(|x| x.parse().ok()),
+                timestamp: attribute("packet_timeout_timestamp")
+                    .and_then(|x| x.parse::<u64>().ok())
+                    .filter(|x| *x > 0)
+                    .map(Duration::from_nanos),
+            })
+        })
+}
+
+/// Whether the endpoint, rather than the query, failed. Statuses tonic makes
+/// from transport errors and expired request timeouts carry their cause as
+/// source, unlike the statuses a node returns, whose `Unknown` stands for an
+/// ordinary application error.
+fn is_connection_error(status: &Status) -> bool {
+    status.code() == Code::Unavailable
+        || (matches!(status.code(), Code::Unknown | Code::Cancelled)
+            && std::error::Error::source(status).is_some())
+}
+
+/// Helper function to create a gRPC channel.
+pub async fn create_grpc_channel(
     grpc_addr: Uri,
-    client_constructor: impl FnOnce(tonic::transport::Channel) -> T,
-) -> Result<T, crate::error::Error> {
+    connect_timeout: Duration,
+    timeout: Duration,
+) -> Result<Channel, crate::error::Error> {
     let tls_config = tonic::transport::ClientTlsConfig::new().with_native_roots();
-    let channel = tonic::transport::Channel::builder(grpc_addr)
+    Channel::builder(grpc_addr)
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
         .tls_config(tls_config)
         .map_err(crate::error::Error::grpc_transport)?
         .connect()
         .await
-        .map_err(crate::error::Error::grpc_transport)?;
-    Ok(client_constructor(channel))
+        .map_err(crate::error::Error::grpc_transport)
 }
 
 #[cfg(test)]
@@ -160,9 +938,12 @@ mod tests {
     async fn test_get_packet_commitments_total() {
         let port_id = "transfer".to_string();
         let channel_id = "channel-0".to_string();
-        let grpc_addr = "https://grpc.mantrachain.io".to_string();
-        let total = get_packet_commitments_total(port_id, channel_id, grpc_addr)
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let total = client
+            .get_packet_commitments_total(port_id, channel_id)
             .await
+            .result
             .unwrap();
         println!("{:?}", total);
         assert_ge!(total, 0);
@@ -172,9 +953,12 @@ mod tests {
     async fn test_get_trusting_period() {
         let port_id = "transfer".to_string();
         let channel_id = "channel-0".to_string();
-        let grpc_addr = "https://grpc.mantrachain.io".to_string();
-        let duration = get_trusting_period(port_id, channel_id, grpc_addr)
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let duration = client
+            .get_trusting_period(port_id, channel_id)
             .await
+            .result
             .unwrap();
         println!("{:?}", duration);
         assert_ge!(duration.as_secs(), 0);
@@ -184,9 +968,12 @@ mod tests {
     async fn test_get_latest_channel_client_state_height() {
         let port_id = "transfer".to_string();
         let channel_id = "channel-0".to_string();
-        let grpc_addr = "https://grpc.mantrachain.io".to_string();
-        let height = get_latest_channel_client_state_height(port_id, channel_id, grpc_addr)
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let height = client
+            .get_latest_channel_client_state_height(port_id, channel_id)
             .await
+            .result
             .unwrap();
         println!("{:?}", height);
         assert_ge!(height.revision_height(), 0);
@@ -196,28 +983,191 @@ mod tests {
     async fn test_get_latest_channel_client_consensus_state_duration() {
         let port_id = "transfer".to_string();
         let channel_id = "channel-0".to_string();
-        let grpc_addr = "https://grpc.mantrachain.io".to_string();
-        let height = get_latest_channel_client_state_height(
-            port_id.clone(),
-            channel_id.clone(),
-            grpc_addr.clone(),
-        )
-        .await
-        .unwrap();
-        let duration = get_latest_channel_client_consensus_state_duration(
-            port_id, channel_id, height, grpc_addr,
-        )
-        .await
-        .unwrap();
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let height = client
+            .get_latest_channel_client_state_height(port_id.clone(), channel_id.clone())
+            .await
+            .result
+            .unwrap();
+        let duration = client
+            .get_latest_channel_client_consensus_state_duration(port_id, channel_id, height)
+            .await
+            .result
+            .unwrap();
         println!("{:?}", duration);
         assert_ge!(duration.as_secs(), 0);
     }
 
+    #[actix_rt::test]
+    async fn test_get_client_status() {
+        let port_id = "transfer".to_string();
+        let channel_id = "channel-0".to_string();
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let client_state = client
+            .get_channel_client_state(port_id, channel_id)
+            .await
+            .result
+            .unwrap();
+        let status = client
+            .get_client_status(client_state.client_id.to_string())
+            .await
+            .result
+            .unwrap();
+        println!("{:?}", status);
+        assert_ne!(status, ClientStatus::Unknown);
+    }
+
     #[actix_rt::test]
     async fn test_get_latest_height() {
-        let grpc_addr = "https://grpc.mantrachain.io".to_string();
-        let height = get_latest_height(grpc_addr).await.unwrap();
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let height = client.get_latest_height().await.result.unwrap();
         println!("{:?}", height);
         assert_ge!(height, 0);
     }
+
+    #[actix_rt::test]
+    async fn test_get_packet_commitment_sequences() {
+        let port_id = "transfer".to_string();
+        let channel_id = "channel-0".to_string();
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let sequences = client
+            .get_packet_commitment_sequences(port_id, channel_id)
+            .await
+            .result
+            .unwrap();
+        println!("{:?}", sequences);
+        assert_ge!(sequences.len(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_channel() {
+        let port_id = "transfer".to_string();
+        let channel_id = "channel-0".to_string();
+        let client =
+            ChainClient::new("mantra-1", &["https://grpc.mantrachain.io".to_string()]).unwrap();
+        let channel = client
+            .get_channel(port_id, channel_id)
+            .await
+            .result
+            .unwrap();
+        println!("{:?}", channel);
+        assert!(channel.counterparty.is_some());
+    }
+
+    #[test]
+    fn test_packet_timeout_from_tx() {
+        use ibc_proto::cosmos::base::abci::v1beta1::{AbciMessageLog, Attribute, StringEvent};
+
+        let attributes = |sequence: &str| {
+            [
+                ("packet_src_port", "transfer"),
+                ("packet_src_channel", "channel-0"),
+                ("packet_sequence", sequence),
+                ("packet_timeout_height", "1-1200"),
+                ("packet_timeout_timestamp", "1700000000000000000"),
+            ]
+            .iter()
+            .map(|(key, value)| Attribute {
+                key: key.to_string(),
+                value: value.to_string(),
+            })
+            .collect()
+        };
+        let tx = TxResponse {
+            logs: vec![AbciMessageLog {
+                events: vec![
+                    StringEvent {
+                        r#type: "send_packet".to_string(),
+                        attributes: attributes("7"),
+                    },
+                    StringEvent {
+                        r#type: "send_packet".to_string(),
+                        attributes: attributes("8"),
+                    },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            packet_timeout_from_tx(&tx, "transfer", "channel-0", 8),
+            Some(PacketTimeout {
+                height: Some(Height::new(1, 1200).unwrap()),
+                timestamp: Some(Duration::from_secs(1_700_000_000)),
+            })
+        );
+        assert_eq!(
+            packet_timeout_from_tx(&tx, "transfer", "channel-1", 8),
+            None
+        );
+        assert_eq!(
+            packet_timeout_from_tx(&tx, "transfer", "channel-0", 9),
+            None
+        );
+    }
+
+    #[test]
+    fn test_endpoint_order_rotates_over_healthy_endpoints() {
+        let client = ChainClient::new(
+            "test-1",
+            &[
+                "http://127.0.0.1:9090".to_string(),
+                "http://127.0.0.1:9091".to_string(),
+                "http://127.0.0.1:9092".to_string(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(client.endpoint_order(), vec![0, 1, 2]);
+        assert_eq!(client.endpoint_order(), vec![1, 2, 0]);
+        assert_eq!(client.endpoint_order(), vec![2, 0, 1]);
+
+        // a failed endpoint is asked last until it recovers
+        client.endpoints[1].record_failure();
+        assert_eq!(client.endpoint_order(), vec![0, 2, 1]);
+        assert_eq!(client.endpoint_order(), vec![2, 0, 1]);
+        assert_eq!(client.endpoint_order(), vec![2, 0, 1]);
+
+        client.endpoints[1].record_success();
+        assert_eq!(client.endpoint_order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_application_errors_keep_the_connection() {
+        assert!(!is_connection_error(&Status::unknown("account not found")));
+        assert!(!is_connection_error(&Status::cancelled(
+            "canceled by the node"
+        )));
+        assert!(is_connection_error(&Status::unavailable(
+            "connection refused"
+        )));
+        assert!(is_connection_error(&Status::from_error(Box::new(
+            tonic::TimeoutExpired(())
+        ))));
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_calls_are_counted() {
+        let client =
+            ChainClient::new("query-metrics-1", &["http://127.0.0.1:1".to_string()]).unwrap();
+        let queried = client.get_latest_height().await;
+        assert!(queried.result.is_err());
+        assert_eq!(queried.endpoint, "http://127.0.0.1:1");
+
+        let failures = crate::telemetry::metrics()
+            .ibc_query_failures
+            .with_label_values(&[
+                "query-metrics-1",
+                "http://127.0.0.1:1",
+                "cosmos.base.tendermint.v1beta1.Service/GetLatestBlock",
+                "Unavailable",
+            ])
+            .get();
+        // a single endpoint is tried twice
+        assert_eq!(failures, 2);
+    }
 }
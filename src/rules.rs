@@ -0,0 +1,479 @@
+//! Prometheus alerting rules for the metrics of the watcher, generated from
+//! the config so the rules follow the configured chains and refresh intervals
+use crate::config::{Config, PrometheusConfig};
+use serde::Serialize;
+use std::{collections::BTreeMap, time::Duration};
+
+/// Name of the rule group
+const GROUP: &str = "ibc-watcher";
+
+/// How a rule file is wrapped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// a plain rule file for `rule_files`
+    Rules,
+    /// a PrometheusRule resource of the Prometheus Operator
+    PrometheusRule,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rules" => Ok(Format::Rules),
+            "prometheus-rule" => Ok(Format::PrometheusRule),
+            _ => Err(format!(
+                "unknown format {:?}, expected rules or prometheus-rule",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RuleFile {
+    pub groups: Vec<RuleGroup>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RuleGroup {
+    pub name: String,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Rule {
+    pub alert: String,
+    pub expr: String,
+    #[serde(rename = "for")]
+    pub for_: String,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PrometheusRule<'a> {
+    api_version: &'static str,
+    kind: &'static str,
+    metadata: Metadata<'a>,
+    spec: &'a RuleFile,
+}
+
+#[derive(Serialize)]
+struct Metadata<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<&'a str>,
+}
+
+/// Options of `ibc-watcher gen-rules`
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// links every alert to `{runbook_url}/{alert}` when set
+    pub runbook_url: Option<String>,
+    /// name of the PrometheusRule resource, the group name when unset
+    pub name: Option<String>,
+    pub namespace: Option<String>,
+}
+
+/// An alert and the metric it follows
+struct Definition {
+    alert: &'static str,
+    /// the metrics the expression reads, with `{ns}` for the namespace and
+    /// `{selector}` for the chains
+    expr: &'static str,
+    /// pending time when the alert is not bound to the refresh of a channel
+    for_: Duration,
+    /// whether the metrics are updated every channel refresh, the pending
+    /// time then spans at least two refreshes
+    per_channel: bool,
+    severity: &'static str,
+    summary: &'static str,
+    runbook: &'static str,
+}
+
+const DEFINITIONS: &[Definition] = &[
+    Definition {
+        alert: "IbcPacketCommitmentsAboveThreshold",
+        expr: "{ns}ibc_count{selector} and {ns}ibc_status{selector} == 1",
+        for_: Duration::from_secs(5 * 60),
+        per_channel: true,
+        severity: "warning",
+        summary: "{{ $value }} packet commitments on {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }}, at least its min_total",
+        runbook: "Packets are not relayed to the destination chain. Check that the relayer of the channel is running, funded and connected to both chains.",
+    },
+    Definition {
+        alert: "IbcPacketsNotReceived",
+        expr: "{ns}ibc_unreceived_packets{selector} > 0",
+        for_: Duration::from_secs(30 * 60),
+        per_channel: true,
+        severity: "warning",
+        summary: "{{ $value }} packets from {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} not received on {{ $labels.destination_chain_id }}",
+        runbook: "The relayer does not submit MsgRecvPacket on the destination chain. Check its logs for errors on the destination chain.",
+    },
+    Definition {
+        alert: "IbcPacketsNotAcknowledged",
+        expr: "{ns}ibc_received_unacknowledged_packets{selector} > 0",
+        for_: Duration::from_secs(30 * 60),
+        per_channel: true,
+        severity: "warning",
+        summary: "{{ $value }} packets from {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} received on {{ $labels.destination_chain_id }} but not acknowledged",
+        runbook: "The relayer does not relay the acknowledgements back to the source chain. Check its logs for errors on the source chain.",
+    },
+    Definition {
+        alert: "IbcUnrelayedAcksAboveThreshold",
+        expr: "{ns}ibc_unrelayed_acks{selector} and {ns}ibc_unrelayed_acks_status{selector} == 1",
+        for_: Duration::from_secs(5 * 60),
+        per_channel: true,
+        severity: "warning",
+        summary: "{{ $value }} acknowledgements of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} not relayed back from {{ $labels.destination_chain_id }}",
+        runbook: "Acknowledgements written on the destination chain are not relayed back. Check the relayer logs and its balance on the source chain.",
+    },
+    Definition {
+        alert: "IbcPacketTooOld",
+        expr: "{ns}ibc_oldest_packet_age_seconds{selector} and {ns}ibc_packet_age_status{selector} == 1",
+        for_: Duration::ZERO,
+        per_channel: true,
+        severity: "warning",
+        summary: "The oldest packet of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} waits for {{ $value | humanizeDuration }}",
+        runbook: "A packet is stuck longer than max_packet_age. Check whether the relayer skips it, e.g. because of a packet filter or a failing transaction.",
+    },
+    Definition {
+        alert: "IbcPacketsNearTimeout",
+        expr: "{ns}ibc_packets_near_timeout{selector} > 0",
+        for_: Duration::ZERO,
+        per_channel: true,
+        severity: "warning",
+        summary: "{{ $value }} packets of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} time out on {{ $labels.destination_chain_id }} within timeout_window",
+        runbook: "Relay the packets before they time out, or make sure the relayer times them out so the funds are refunded.",
+    },
+    Definition {
+        alert: "IbcPacketsTimedOut",
+        expr: "increase({ns}ibc_packets_timed_out_total{selector}[1h]) > 0",
+        for_: Duration::ZERO,
+        per_channel: false,
+        severity: "warning",
+        summary: "{{ $value }} packets of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} timed out without being relayed in the last hour",
+        runbook: "Packets passed their timeout. Check that the relayer submits MsgTimeout so the senders are refunded.",
+    },
+    Definition {
+        alert: "IbcWatcherTaskRestarting",
+        expr: "increase({ns}ibc_watcher_task_restarts_total{selector}[15m]) > 3",
+        for_: Duration::ZERO,
+        per_channel: false,
+        severity: "warning",
+        summary: "The {{ $labels.task }} task of {{ $labels.chain_id }} restarted {{ $value }} times in 15 minutes",
+        runbook: "A tracker task keeps crashing. Check the watcher logs for the panic or error of the task.",
+    },
+    Definition {
+        alert: "IbcChannelNotOpen",
+        expr: "{ns}ibc_channel_state{selector} != 3",
+        for_: Duration::ZERO,
+        per_channel: true,
+        severity: "critical",
+        summary: "Channel {{ $labels.port_id }}/{{ $labels.channel_id }} of {{ $labels.chain_id }} is in state {{ $value }}, 3 is open",
+        runbook: "The channel no longer relays packets. Find out whether it was closed on purpose or is still in its handshake.",
+    },
+    Definition {
+        alert: "IbcConnectionNotOpen",
+        expr: "{ns}ibc_connection_state{selector} != 3",
+        for_: Duration::ZERO,
+        per_channel: true,
+        severity: "critical",
+        summary: "Connection {{ $labels.connection_id }} of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} is in state {{ $value }}, 3 is open",
+        runbook: "The connection of the channel is not open, no packet can be relayed. Check its handshake with the relayer.",
+    },
+    Definition {
+        alert: "IbcQueryFailing",
+        expr: "{ns}ibc_query_status{selector} == 1",
+        for_: Duration::from_secs(10 * 60),
+        per_channel: true,
+        severity: "warning",
+        summary: "Queries of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} fail on {{ $labels.query_endpoint_url }}",
+        runbook: "The watcher cannot query the gRPC endpoint. Check that the node is up and reachable, or add fallback_grpc_addrs.",
+    },
+    Definition {
+        alert: "IbcClientExpiring",
+        expr: "{ns}ibc_client_time_before_expire{selector} and {ns}ibc_client_status{selector} == 1",
+        for_: Duration::ZERO,
+        per_channel: true,
+        severity: "critical",
+        summary: "The client of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} expires in {{ $value | humanizeDuration }}",
+        runbook: "Update the client before it expires, e.g. with `hermes update client`. An expired client needs a governance proposal to recover.",
+    },
+    Definition {
+        alert: "IbcClientNotActive",
+        expr: "{ns}ibc_client_onchain_status{selector} != 0",
+        for_: Duration::ZERO,
+        per_channel: true,
+        severity: "critical",
+        summary: "Client {{ $labels.client_id }} of {{ $labels.chain_id }} {{ $labels.port_id }}/{{ $labels.channel_id }} is in status {{ $value }}, 1: Expired, 2: Frozen, 3: Unauthorized",
+        runbook: "The chain no longer accepts updates of the client. An expired or frozen client needs a governance proposal to substitute it.",
+    },
+    Definition {
+        alert: "IbcNodeNotSynced",
+        expr: "{ns}ibc_query_node_sync_status{selector} == 1",
+        for_: Duration::from_secs(5 * 60),
+        per_channel: false,
+        severity: "critical",
+        summary: "The node queried for {{ $labels.chain_id }} does not produce new blocks",
+        runbook: "The queried node is stuck or the chain halted. Check the node and the block explorer of the chain.",
+    },
+    Definition {
+        alert: "IbcQueriesSlow",
+        expr: "histogram_quantile(0.95, sum by (chain_id, endpoint, le) (rate({ns}ibc_query_duration_seconds_bucket{selector}[10m]))) > 5",
+        for_: Duration::from_secs(15 * 60),
+        per_channel: false,
+        severity: "warning",
+        summary: "95% of the queries of {{ $labels.chain_id }} on {{ $labels.endpoint }} take up to {{ $value | humanizeDuration }}",
+        runbook: "The gRPC endpoint is overloaded or far away. Move it behind a faster node or add fallback_grpc_addrs, the watcher prefers healthy endpoints.",
+    },
+    Definition {
+        alert: "IbcQueriesFailing",
+        expr: "sum by (chain_id, endpoint, code) (increase({ns}ibc_query_failures_total{selector}[10m])) > 5",
+        for_: Duration::from_secs(10 * 60),
+        per_channel: false,
+        severity: "warning",
+        summary: "{{ $value }} queries of {{ $labels.chain_id }} on {{ $labels.endpoint }} failed with {{ $labels.code }} in 10 minutes",
+        runbook: "Queries keep failing on the endpoint, even if a fallback answers them. Check the node, its gRPC settings and whether it prunes the queried state.",
+    },
+];
+
+/// The rules of every metric, limited to the configured chains
+pub fn generate(config: &Config, options: &Options) -> RuleFile {
+    let namespace = match &config.prometheus.namespace {
+        Some(namespace) => format!("{}_", namespace),
+        None => String::new(),
+    };
+    let selector = selector(config);
+    let longest_refresh = config
+        .chains
+        .iter()
+        .flat_map(|chain| {
+            chain
+                .channels
+                .iter()
+                .map(|x| x.refresh)
+                .chain(chain.discovery.as_ref().map(|x| x.refresh))
+        })
+        .max()
+        .unwrap_or_default();
+
+    let rules = DEFINITIONS
+        .iter()
+        .map(|definition| {
+            let for_ = if definition.per_channel {
+                definition.for_.max(longest_refresh * 2)
+            } else {
+                definition.for_
+            };
+            let mut annotations = BTreeMap::from([
+                ("summary".to_string(), definition.summary.to_string()),
+                ("runbook".to_string(), definition.runbook.to_string()),
+            ]);
+            if let Some(runbook_url) = &options.runbook_url {
+                annotations.insert(
+                    "runbook_url".to_string(),
+                    format!(
+                        "{}/{}",
+                        runbook_url.trim_end_matches('/'),
+                        definition.alert.to_lowercase()
+                    ),
+                );
+            }
+            Rule {
+                alert: definition.alert.to_string(),
+                expr: definition
+                    .expr
+                    .replace("{ns}", &namespace)
+                    .replace("{selector}", &selector),
+                for_: prometheus_duration(for_),
+                labels: BTreeMap::from([("severity".to_string(), definition.severity.to_string())]),
+                annotations,
+            }
+        })
+        .collect();
+    RuleFile {
+        groups: vec![RuleGroup {
+            name: GROUP.to_string(),
+            rules,
+        }],
+    }
+}
+
+/// Renders the rules as YAML in the given format
+pub fn to_yaml(rules: &RuleFile, format: Format, options: &Options) -> String {
+    match format {
+        Format::Rules => serde_yaml::to_string(rules),
+        Format::PrometheusRule => serde_yaml::to_string(&PrometheusRule {
+            api_version: "monitoring.coreos.com/v1",
+            kind: "PrometheusRule",
+            metadata: Metadata {
+                name: options.name.as_deref().unwrap_or(GROUP),
+                namespace: options.namespace.as_deref(),
+            },
+            spec: rules,
+        }),
+    }
+    .expect("rules can be serialized")
+}
+
+/// Matches the configured chains, as the destination of a channel can be a
+/// chain which is not watched itself, and the constant labels of this watcher
+fn selector(config: &Config) -> String {
+    let chain_ids: Vec<String> = config.chains.iter().map(|x| regex_escape(&x.id)).collect();
+    let mut matchers = Vec::new();
+    if !chain_ids.is_empty() {
+        matchers.push(format!("chain_id=~{}", promql_string(&chain_ids.join("|"))));
+    }
+    matchers.extend(const_label_matchers(&config.prometheus));
+    if matchers.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", matchers.join(","))
+    }
+}
+
+/// Matches the constant labels of this watcher, so the series of watchers
+/// sharing a Prometheus are told apart
+pub(crate) fn const_label_matchers(config: &PrometheusConfig) -> Vec<String> {
+    config
+        .const_labels
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, promql_string(value)))
+        .collect()
+}
+
+fn regex_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quotes the string as a PromQL string literal, other characters than the
+/// escaped ones are valid in it as they are
+fn promql_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Formats a duration the way Prometheus parses it, e.g. `1h30m`
+fn prometheus_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        return "0s".to_string();
+    }
+    [(secs / 3600, "h"), (secs / 60 % 60, "m"), (secs % 60, "s")]
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config, telemetry::Metrics};
+
+    #[test]
+    fn rules_cover_every_metric() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/config/fixtures/chains.toml"
+        );
+        let config = config::load(path).expect("could not parse config");
+        let rules = generate(&config, &Options::default());
+        let rules = &rules.groups[0].rules;
+
+        for collector in Metrics::new(&config.prometheus).collectors() {
+            for desc in collector.desc() {
+                // the thresholds only repeat the config, the status gauges
+                // carry the alerts
+                if desc.fq_name.contains("_threshold") {
+                    continue;
+                }
+                assert!(
+                    rules.iter().any(|x| x.expr.contains(&desc.fq_name)),
+                    "no rule for {}",
+                    desc.fq_name
+                );
+            }
+        }
+        let commitments = &rules[0];
+        assert_eq!(
+            commitments.expr,
+            "ibc_count{chain_id=~\"chain_A|chain_B\",environment=\"testnet\"} and ibc_status{chain_id=~\"chain_A|chain_B\",environment=\"testnet\"} == 1"
+        );
+        // twice the longest refresh of the fixture
+        assert_eq!(commitments.for_, "10m");
+        assert_eq!(commitments.labels["severity"], "warning");
+    }
+
+    #[test]
+    fn render_prometheus_rule() {
+        let options = Options {
+            runbook_url: Some("https://runbooks.example.com/ibc/".to_string()),
+            name: None,
+            namespace: Some("monitoring".to_string()),
+        };
+        let rules = generate(&Config::default(), &options);
+        let yaml = to_yaml(&rules, Format::PrometheusRule, &options);
+        let value: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(value["kind"], "PrometheusRule");
+        assert_eq!(value["metadata"]["namespace"], "monitoring");
+        let rule = &value["spec"]["groups"][0]["rules"][0];
+        assert_eq!(rule["expr"], "ibc_count and ibc_status == 1");
+        assert_eq!(rule["for"], "5m");
+        assert_eq!(
+            rule["annotations"]["runbook_url"],
+            "https://runbooks.example.com/ibc/ibcpacketcommitmentsabovethreshold"
+        );
+    }
+
+    #[test]
+    fn namespace_and_const_labels() {
+        let mut config = Config::default();
+        config.prometheus.namespace = Some("mantra".to_string());
+        config.prometheus.const_labels =
+            [("environment".to_string(), "testnet".to_string())].into();
+        let rules = generate(&config, &Options::default());
+        assert_eq!(
+            rules.groups[0].rules[1].expr,
+            "mantra_ibc_unreceived_packets{environment=\"testnet\"} > 0"
+        );
+    }
+
+    #[test]
+    fn durations_and_selectors() {
+        assert_eq!(prometheus_duration(Duration::from_secs(5400)), "1h30m");
+        assert_eq!(prometheus_duration(Duration::from_secs(61)), "1m1s");
+        assert_eq!(regex_escape("chain.A-1"), "chain\\.A-1");
+        assert_eq!(
+            promql_string(&regex_escape("chain.A-1")),
+            "\"chain\\\\.A-1\""
+        );
+        let mut config = Config::default();
+        config.prometheus.const_labels =
+            [("environment".to_string(), "test \"net\" ü\n".to_string())].into();
+        assert_eq!(selector(&config), "{environment=\"test \\\"net\\\" ü\\n\"}");
+    }
+}
@@ -0,0 +1,418 @@
+//! In-memory state of the chains and channels, updated by the trackers next
+//! to the gauges and served as JSON on /api/v1/status, plus the liveness of
+//! the collector and its tasks behind /healthz and /readyz
+use crate::{config, error::summary};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+use warp::{http::StatusCode, Rejection, Reply};
+
+/// How often the collector reports that it is alive
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// The collector counts as dead when it missed this many heartbeats
+const MISSED_HEARTBEATS: u32 = 3;
+/// A task down for longer than this fails the readiness, shorter outages are
+/// covered by its restart
+const TASK_DOWN_GRACE: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    static ref STATUS: Mutex<BTreeMap<String, ChainStatus>> = Mutex::new(BTreeMap::new());
+    static ref HEALTH: Mutex<Health> = Mutex::new(Health::default());
+}
+
+#[derive(Default)]
+struct Health {
+    collector_heartbeat: Option<Instant>,
+    /// supervised tasks which crashed and did not recover yet, with the time
+    /// of their first crash
+    tasks_down: BTreeMap<String, Instant>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Status {
+    pub chains: Vec<ChainStatus>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChainStatus {
+    pub chain_id: String,
+    /// the gRPC endpoint answering the latest query
+    pub grpc_endpoint: Option<String>,
+    pub latest_height: Option<i64>,
+    /// false when the height did not move since the previous poll
+    pub node_synced: Option<bool>,
+    #[serde(with = "humantime_serde")]
+    pub last_success: Option<SystemTime>,
+    pub last_error: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub last_error_time: Option<SystemTime>,
+    pub channels: Vec<ChannelStatus>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChannelStatus {
+    pub port_id: String,
+    pub channel_id: String,
+    pub destination_chain_id: String,
+    pub commitments: Option<u64>,
+    pub min_total: String,
+    pub client_id: Option<String>,
+    /// the status the chain reports for the client
+    pub client_status: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub client_expiry_time: Option<SystemTime>,
+    #[serde(with = "humantime_serde")]
+    pub trusting_period: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    pub min_time_before_client_expiration: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    pub last_success: Option<SystemTime>,
+    /// the latest of `errors`
+    pub last_error: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub last_error_time: Option<SystemTime>,
+    /// the error of every failing query of the channel, by query, cleared by
+    /// the next success of that query
+    pub errors: BTreeMap<String, QueryError>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueryError {
+    pub error: String,
+    #[serde(with = "humantime_serde")]
+    pub time: SystemTime,
+}
+
+impl ChainStatus {
+    /// Records a success, which clears the previous error
+    pub fn succeeded(&mut self) {
+        self.last_success = Some(SystemTime::now());
+        self.last_error = None;
+        self.last_error_time = None;
+    }
+
+    pub fn failed(&mut self, e: &impl fmt::Display) {
+        self.last_error = Some(summary(e));
+        self.last_error_time = Some(SystemTime::now());
+    }
+}
+
+impl ChannelStatus {
+    /// Records a success of the query, which clears its previous error only,
+    /// the other queries of the channel may still fail
+    pub fn succeeded(&mut self, query: &str) {
+        self.last_success = Some(SystemTime::now());
+        self.errors.remove(query);
+        self.update_last_error();
+    }
+
+    pub fn failed(&mut self, query: &str, e: &impl fmt::Display) {
+        self.errors.insert(
+            query.to_string(),
+            QueryError {
+                error: summary(e),
+                time: SystemTime::now(),
+            },
+        );
+        self.update_last_error();
+    }
+
+    fn update_last_error(&mut self) {
+        let last = self.errors.values().max_by_key(|x| x.time);
+        self.last_error = last.map(|x| x.error.clone());
+        self.last_error_time = last.map(|x| x.time);
+    }
+}
+
+/// Updates the state of the chain, creating it if it is not known yet
+pub fn update_chain(chain_id: &str, f: impl FnOnce(&mut ChainStatus)) {
+    let mut status = STATUS.lock().unwrap();
+    let chain = status
+        .entry(chain_id.to_string())
+        .or_insert_with(|| ChainStatus {
+            chain_id: chain_id.to_string(),
+            ..Default::default()
+        });
+    f(chain);
+}
+
+/// Updates the state of the channel, creating it if it is not known yet. The
+/// destination and threshold are taken from the channel config.
+pub fn update_channel(
+    chain_id: &str,
+    chain_channel: &config::Channel,
+    f: impl FnOnce(&mut ChannelStatus),
+) {
+    update_chain(chain_id, |chain| {
+        let index = match chain.channels.iter().position(|x| {
+            x.port_id == chain_channel.port_id && x.channel_id == chain_channel.channel_id
+        }) {
+            Some(index) => index,
+            None => {
+                chain.channels.push(ChannelStatus {
+                    port_id: chain_channel.port_id.clone(),
+                    channel_id: chain_channel.channel_id.clone(),
+                    ..Default::default()
+                });
+                chain.channels.len() - 1
+            }
+        };
+        let channel = &mut chain.channels[index];
+        channel.destination_chain_id = chain_channel.destination_chain_id.clone();
+        channel.min_total = chain_channel.min_total.clone();
+        f(channel);
+    });
+}
+
+pub fn remove_chain(chain_id: &str) {
+    STATUS.lock().unwrap().remove(chain_id);
+}
+
+pub fn remove_channel(chain_id: &str, port_id: &str, channel_id: &str) {
+    if let Some(chain) = STATUS.lock().unwrap().get_mut(chain_id) {
+        chain
+            .channels
+            .retain(|x| x.port_id != port_id || x.channel_id != channel_id);
+    }
+}
+
+/// A copy of the current state, chains ordered by id
+pub fn snapshot() -> Status {
+    Status {
+        chains: STATUS.lock().unwrap().values().cloned().collect(),
+    }
+}
+
+pub async fn status_handler() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&snapshot()))
+}
+
+/// Reports that the collector is alive, due every HEARTBEAT_INTERVAL
+pub fn collector_heartbeat() {
+    HEALTH.lock().unwrap().collector_heartbeat = Some(Instant::now());
+}
+
+/// Reports that the collector stopped, e.g. on shutdown
+pub fn collector_stopped() {
+    HEALTH.lock().unwrap().collector_heartbeat = None;
+}
+
+pub fn task_down(task: String) {
+    HEALTH
+        .lock()
+        .unwrap()
+        .tasks_down
+        .entry(task)
+        .or_insert_with(Instant::now);
+}
+
+pub fn task_up(task: &str) {
+    HEALTH.lock().unwrap().tasks_down.remove(task);
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Liveness {
+    pub alive: bool,
+    #[serde(with = "humantime_serde")]
+    pub collector_heartbeat_age: Option<Duration>,
+}
+
+/// Alive while the collector sends its heartbeats. A crashed tracker task is
+/// restarted by its supervisor, so it is reported by `readiness` instead.
+pub fn liveness() -> Liveness {
+    let health = HEALTH.lock().unwrap();
+    let collector_heartbeat_age = health.collector_heartbeat.map(|x| x.elapsed());
+    let collector_alive =
+        collector_heartbeat_age.is_some_and(|age| age < HEARTBEAT_INTERVAL * MISSED_HEARTBEATS);
+    Liveness {
+        alive: collector_alive,
+        collector_heartbeat_age,
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// chains without a successful query, or none within the staleness
+    pub chains_not_ready: Vec<String>,
+    /// supervised tasks which crashed and did not recover yet
+    pub tasks_down: Vec<String>,
+}
+
+/// Ready once every configured chain answered a query, within `staleness`
+/// when given, and while no task is down for longer than TASK_DOWN_GRACE
+pub fn readiness(staleness: Option<Duration>) -> Readiness {
+    let now = SystemTime::now();
+    let chains_not_ready: Vec<String> = STATUS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|chain| {
+            let last_success = chain
+                .channels
+                .iter()
+                .filter_map(|x| x.last_success)
+                .chain(chain.last_success)
+                .max();
+            match (last_success, staleness) {
+                (None, _) => true,
+                (Some(last_success), Some(staleness)) => now
+                    .duration_since(last_success)
+                    .is_ok_and(|age| age > staleness),
+                (Some(_), None) => false,
+            }
+        })
+        .map(|chain| chain.chain_id.clone())
+        .collect();
+    let health = HEALTH.lock().unwrap();
+    let tasks_failing = health
+        .tasks_down
+        .values()
+        .any(|since| since.elapsed() > TASK_DOWN_GRACE);
+    Readiness {
+        ready: chains_not_ready.is_empty() && !tasks_failing,
+        chains_not_ready,
+        tasks_down: health.tasks_down.keys().cloned().collect(),
+    }
+}
+
+pub async fn liveness_handler() -> Result<impl Reply, Rejection> {
+    let liveness = liveness();
+    let code = if liveness.alive {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&liveness), code))
+}
+
+pub async fn readiness_handler(staleness: Option<Duration>) -> Result<impl Reply, Rejection> {
+    let readiness = readiness(staleness);
+    let code = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&readiness),
+        code,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_and_remove_channel() {
+        let chain_channel = config::Channel {
+            port_id: "transfer".to_string(),
+            channel_id: "channel-0".to_string(),
+            destination_chain_id: "status-2".to_string(),
+            min_time_before_client_expiration: None,
+            min_total: "10".to_string(),
+            min_unrelayed_acks: None,
+            max_packet_age: None,
+            timeout_window: None,
+            refresh: config::default::refresh(),
+        };
+        update_channel("status-1", &chain_channel, |x| {
+            x.commitments = Some(3);
+            x.succeeded("packet_commitments");
+        });
+        update_channel("status-1", &chain_channel, |x| {
+            x.failed("client_status", &"timeout\ncause")
+        });
+        // another query of the channel keeps the error
+        update_channel("status-1", &chain_channel, |x| {
+            x.succeeded("packet_commitments")
+        });
+
+        let status = snapshot();
+        let chain = status
+            .chains
+            .iter()
+            .find(|x| x.chain_id == "status-1")
+            .unwrap();
+        assert_eq!(chain.channels.len(), 1);
+        let channel = &chain.channels[0];
+        assert_eq!(channel.commitments, Some(3));
+        assert_eq!(channel.min_total, "10");
+        assert!(channel.last_success.is_some());
+        assert_eq!(channel.last_error.as_deref(), Some("timeout"));
+
+        update_channel("status-1", &chain_channel, |x| x.succeeded("client_status"));
+        let status = snapshot();
+        let chain = status
+            .chains
+            .iter()
+            .find(|x| x.chain_id == "status-1")
+            .unwrap();
+        assert_eq!(chain.channels[0].last_error, None);
+        assert!(chain.channels[0].errors.is_empty());
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert!(json["chains"][0]["channels"][0]["last_success"].is_string());
+
+        remove_channel("status-1", "transfer", "channel-0");
+        assert!(snapshot()
+            .chains
+            .iter()
+            .any(|x| x.chain_id == "status-1" && x.channels.is_empty()));
+        remove_chain("status-1");
+    }
+
+    #[test]
+    fn readiness_needs_a_recent_success() {
+        update_chain("ready-1", |_| {});
+        assert!(readiness(None)
+            .chains_not_ready
+            .contains(&"ready-1".to_string()));
+
+        update_chain("ready-1", |x| {
+            x.last_success = Some(SystemTime::now() - Duration::from_secs(120))
+        });
+        assert!(!readiness(None)
+            .chains_not_ready
+            .contains(&"ready-1".to_string()));
+        assert!(readiness(Some(Duration::from_secs(60)))
+            .chains_not_ready
+            .contains(&"ready-1".to_string()));
+        remove_chain("ready-1");
+    }
+
+    #[test]
+    fn tasks_down_are_reported_without_failing_liveness() {
+        collector_heartbeat();
+        task_down("test task of (live-1)".to_string());
+        let liveness = liveness();
+        assert!(liveness.alive);
+        assert!(liveness.collector_heartbeat_age.is_some());
+        assert!(readiness(None)
+            .tasks_down
+            .contains(&"test task of (live-1)".to_string()));
+
+        // crash-looping past the grace period
+        if let Some(since) = Instant::now().checked_sub(TASK_DOWN_GRACE * 2) {
+            HEALTH
+                .lock()
+                .unwrap()
+                .tasks_down
+                .insert("test task of (live-1)".to_string(), since);
+            assert!(!readiness(None).ready);
+        }
+
+        task_up("test task of (live-1)");
+        assert!(!readiness(None)
+            .tasks_down
+            .contains(&"test task of (live-1)".to_string()));
+
+        collector_stopped();
+        assert!(!super::liveness().alive);
+    }
+}
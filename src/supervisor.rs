@@ -0,0 +1,187 @@
+//! Runs the tracker tasks, restarts the ones which panic or exit with backoff
+//! and reports the ones which are down to /readyz
+use crate::{status, telemetry::ibc_watcher_task_restarts_incrementer};
+use log::{error, warn};
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinSet;
+
+/// Delay before the first restart of a task, doubled on every consecutive one
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound of the restart delay, a task running longer than this starts
+/// over from MIN_BACKOFF when it crashes again
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// A restarted task counts as up again once it ran this long without crashing
+const RECOVERED_AFTER: Duration = Duration::from_secs(60);
+
+/// Owns a group of tracker tasks and restarts the ones which panic or exit.
+/// Dropping the supervisor aborts all of its tasks.
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: JoinSet<()>,
+}
+
+/// Identifies a supervised task in logs and in ibc_watcher_task_restarts_total,
+/// port_id and channel_id are empty for the tasks of a chain
+#[derive(Clone, Debug, Default)]
+pub struct TaskLabels {
+    pub task: &'static str,
+    pub chain_id: String,
+    pub port_id: String,
+    pub channel_id: String,
+}
+
+impl TaskLabels {
+    pub fn chain(task: &'static str, chain_id: &str) -> Self {
+        Self {
+            task,
+            chain_id: chain_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn channel(task: &'static str, chain_id: &str, port_id: &str, channel_id: &str) -> Self {
+        Self {
+            task,
+            chain_id: chain_id.to_string(),
+            port_id: port_id.to_string(),
+            channel_id: channel_id.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TaskLabels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} task of ({})", self.task, self.chain_id)?;
+        if !self.channel_id.is_empty() {
+            write!(f, " {}/{}", self.port_id, self.channel_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports the task as down to /readyz until dropped, which also covers a
+/// supervisor aborted during the backoff
+struct Down(String);
+
+impl Down {
+    fn new(labels: &TaskLabels) -> Self {
+        let task = labels.to_string();
+        status::task_down(task.clone());
+        Self(task)
+    }
+}
+
+impl Drop for Down {
+    fn drop(&mut self) {
+        status::task_up(&self.0);
+    }
+}
+
+impl Supervisor {
+    /// Runs the future built by `task`, building and running a new one with
+    /// backoff whenever it panics or returns
+    pub fn spawn<F, Fut>(&mut self, labels: TaskLabels, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(supervise(labels, task));
+    }
+
+    /// Aborts all tasks and waits until they are gone
+    pub async fn shutdown(mut self) {
+        self.tasks.shutdown().await;
+    }
+}
+
+async fn supervise<F, Fut>(labels: TaskLabels, task: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut backoff = MIN_BACKOFF;
+    // kept across restarts, so a crash-looping task stays down
+    let mut down: Option<Down> = None;
+    loop {
+        let started = Instant::now();
+        // run the task in its own set, so a panic surfaces as an error here
+        // and aborting the supervisor also aborts the task
+        let mut running = JoinSet::new();
+        running.spawn(task());
+        let joined = loop {
+            tokio::select! {
+                joined = running.join_next() => break joined,
+                _ = tokio::time::sleep(RECOVERED_AFTER), if down.is_some() => down = None,
+            }
+        };
+        match joined {
+            Some(Ok(())) => warn!("{} exited, restarting in {:?}", labels, backoff),
+            Some(Err(e)) if e.is_panic() => {
+                error!("{} panicked, restarting in {:?}", labels, backoff)
+            }
+            _ => return,
+        }
+        down.get_or_insert_with(|| Down::new(&labels));
+        ibc_watcher_task_restarts_incrementer(
+            labels.task,
+            &labels.chain_id,
+            &labels.port_id,
+            &labels.channel_id,
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = next_backoff(backoff, started.elapsed());
+    }
+}
+
+/// Doubles the backoff up to MAX_BACKOFF, or starts over if the task had
+/// been running for longer than that
+fn next_backoff(backoff: Duration, running: Duration) -> Duration {
+    if running > MAX_BACKOFF {
+        MIN_BACKOFF
+    } else {
+        (backoff * 2).min(MAX_BACKOFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn backoff_doubles_and_resets() {
+        let backoff = next_backoff(MIN_BACKOFF, Duration::ZERO);
+        assert_eq!(backoff, Duration::from_secs(2));
+        assert_eq!(
+            next_backoff(Duration::from_secs(200), Duration::ZERO),
+            MAX_BACKOFF
+        );
+        assert_eq!(next_backoff(MAX_BACKOFF, MAX_BACKOFF * 2), MIN_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn restarts_panicked_task() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::default();
+        let counter = runs.clone();
+        supervisor.spawn(TaskLabels::chain("test", "test-1"), move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("first run fails");
+                }
+                std::future::pending::<()>().await;
+            }
+        });
+        tokio::time::sleep(MIN_BACKOFF + Duration::from_millis(500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        supervisor.shutdown().await;
+    }
+}
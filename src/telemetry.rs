@@ -1,103 +1,608 @@
-use lazy_static::lazy_static;
+use crate::config::{Count, PrometheusConfig};
 use log::error;
-use prometheus::{IntGaugeVec, Opts, Registry};
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tonic::Code;
 use warp::{Rejection, Reply};
 
-lazy_static! {
-    pub static ref IBC_STATUS_COLLECTOR: IntGaugeVec = IntGaugeVec::new(
-        Opts::new("ibc_status", "IBC Status. 0: < min_total, 1: > min_total"),
-        &["chain_id", "port_id", "channel_id", "destination_chain_id", "min_total"]
-    )
-    .expect("metric can be created");
-    pub static ref IBC_COUNT_COLLECTOR: IntGaugeVec = IntGaugeVec::new(
-        Opts::new("ibc_count", "no of ibc packet commitments"),
-        &["chain_id", "port_id", "channel_id", "destination_chain_id", "min_total"]
-    )
-    .expect("metric can be created");
-    pub static ref IBC_QUERY_STATUS_COLLECTOR: IntGaugeVec = IntGaugeVec::new(
-        Opts::new("ibc_query_status", "IBC Query Status show the ibc query is successful or not. 0: can access, 1: cannot access"),
-        &["chain_id", "port_id", "channel_id", "destination_chain_id", "query_endpoint_url"]
-    )
-    .expect("metric can be created");
-    pub static ref IBC_CLIENT_STATUS_COLLECTOR: IntGaugeVec = IntGaugeVec::new(
-        Opts::new("ibc_client_status", "IBC client status. 0: (expiry_time - now) > min_time_before_client_expiration, 1: (expiry_time - now) < min_time_before_client_expiration"),
-        &["chain_id", "port_id", "channel_id", "destination_chain_id", "min_time_before_client_expiration"]
-    )
-    .expect("metric can be created");
-    pub static ref IBC_CLIENT_TIME_BEFORE_EXPIRE_COLLECTOR: IntGaugeVec = IntGaugeVec::new(
-        Opts::new("ibc_client_time_before_expire", "the times left before client reach ibc client expiration in seconds"),
-        &["chain_id", "port_id", "channel_id", "destination_chain_id", "min_time_before_client_expiration"]
-    )
-    .expect("metric can be created");
-
-    pub static ref IBC_QUERY_NODE_SYNC_STATUS_COLLECTOR: IntGaugeVec = IntGaugeVec::new(
-        Opts::new("ibc_query_node_sync_status", "IBC query node sync status. 0: blockheight is moving, 1: blockheight is not synced"),
-        &["chain_id"]
-    )
-    .expect("metric can be created");
-
-    pub static ref REGISTRY: Registry = Registry::new();
-}
-
-/// A setter for IBC_STATUS_COLLECTOR, make sure all the labels are set and types are correct
+/// The collectors of the watcher, named and labelled after the prometheus
+/// config
+pub struct Metrics {
+    pub ibc_status: IntGaugeVec,
+    pub ibc_count: IntGaugeVec,
+    pub ibc_unreceived_packets: IntGaugeVec,
+    pub ibc_received_unacknowledged_packets: IntGaugeVec,
+    pub ibc_unrelayed_acks: IntGaugeVec,
+    pub ibc_unrelayed_acks_status: IntGaugeVec,
+    pub ibc_oldest_packet_age: IntGaugeVec,
+    pub ibc_packet_age_status: IntGaugeVec,
+    pub ibc_packets_near_timeout: IntGaugeVec,
+    pub ibc_packets_timed_out: IntCounterVec,
+    pub ibc_watcher_task_restarts: IntCounterVec,
+    pub ibc_channel_state: IntGaugeVec,
+    pub ibc_connection_state: IntGaugeVec,
+    pub ibc_query_status: IntGaugeVec,
+    pub ibc_client_status: IntGaugeVec,
+    pub ibc_client_time_before_expire: IntGaugeVec,
+    pub ibc_client_onchain_status: IntGaugeVec,
+    pub ibc_query_node_sync_status: IntGaugeVec,
+    pub ibc_query_duration: HistogramVec,
+    pub ibc_query_failures: IntCounterVec,
+    pub ibc_count_threshold: IntGaugeVec,
+    pub ibc_client_expiry_threshold: IntGaugeVec,
+    pub registry: Registry,
+    /// whether the thresholds are labels of the status, count and client
+    /// gauges as well
+    pub threshold_labels: bool,
+    /// when each gauge series was last set, keyed by metric name and label
+    /// values
+    updated: Mutex<HashMap<(String, Vec<String>), Instant>>,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Upper bounds of the query duration buckets in seconds, up to the gRPC
+/// timeouts of slow nodes
+const QUERY_DURATION_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// The variable labels of the metrics, which the constant labels must not
+/// repeat
+pub const VARIABLE_LABELS: [&str; 11] = [
+    "chain_id",
+    "port_id",
+    "channel_id",
+    "destination_chain_id",
+    "connection_id",
+    "client_id",
+    "query_endpoint_url",
+    "task",
+    "endpoint",
+    "method",
+    "code",
+];
+
+/// The variable labels holding the threshold of a channel, with
+/// `threshold_labels` only
+pub const THRESHOLD_LABELS: [&str; 2] = ["min_total", "min_time_before_client_expiration"];
+
+impl Metrics {
+    /// Creates the collectors with the namespace and constant labels of the
+    /// config and registers them
+    pub fn new(config: &PrometheusConfig) -> Self {
+        let namespace = config.namespace.clone().unwrap_or_default();
+        let const_labels: HashMap<String, String> = config
+            .const_labels
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let with_threshold = |labels: &[&'static str], threshold: &'static str| {
+            let mut labels = labels.to_vec();
+            if config.threshold_labels {
+                labels.push(threshold);
+            }
+            labels
+        };
+        let opts = |name: &str, help: &str| {
+            Opts::new(name, help)
+                .namespace(namespace.clone())
+                .const_labels(const_labels.clone())
+        };
+        let metrics = Self {
+            ibc_status: IntGaugeVec::new(
+                opts("ibc_status", "IBC Status. 0: < min_total, 1: > min_total"),
+                &with_threshold(&["chain_id", "port_id", "channel_id", "destination_chain_id"], "min_total"),
+            )
+            .expect("metric can be created"),
+            ibc_count: IntGaugeVec::new(
+                opts("ibc_count", "no of ibc packet commitments"),
+                &with_threshold(&["chain_id", "port_id", "channel_id", "destination_chain_id"], "min_total"),
+            )
+            .expect("metric can be created"),
+            ibc_unreceived_packets: IntGaugeVec::new(
+                opts("ibc_unreceived_packets", "no of ibc packet commitments not yet received on the destination chain"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_received_unacknowledged_packets: IntGaugeVec::new(
+                opts("ibc_received_unacknowledged_packets", "no of ibc packets received on the destination chain but not yet acknowledged on the source chain"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_unrelayed_acks: IntGaugeVec::new(
+                opts("ibc_unrelayed_acks", "no of ibc packet acknowledgements written on the destination chain but not yet relayed back"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_unrelayed_acks_status: IntGaugeVec::new(
+                opts("ibc_unrelayed_acks_status", "IBC unrelayed acknowledgements status. 0: < min_unrelayed_acks, 1: >= min_unrelayed_acks"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_oldest_packet_age: IntGaugeVec::new(
+                opts("ibc_oldest_packet_age_seconds", "age in seconds of the oldest ibc packet commitment, counted from when the watcher first saw it"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_packet_age_status: IntGaugeVec::new(
+                opts("ibc_packet_age_status", "IBC packet age status. 0: oldest packet age <= max_packet_age, 1: oldest packet age > max_packet_age"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_packets_near_timeout: IntGaugeVec::new(
+                opts("ibc_packets_near_timeout", "no of outstanding ibc packets timing out on the destination chain within timeout_window"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_packets_timed_out: IntCounterVec::new(
+                opts("ibc_packets_timed_out_total", "no of ibc packets which passed their timeout without being relayed"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_watcher_task_restarts: IntCounterVec::new(
+                opts("ibc_watcher_task_restarts_total", "no of times a tracker task was restarted after it panicked or exited"),
+                &["task", "chain_id", "port_id", "channel_id"],
+            )
+            .expect("metric can be created"),
+            ibc_channel_state: IntGaugeVec::new(
+                opts("ibc_channel_state", "IBC channel state. 0: uninitialized, 1: init, 2: tryopen, 3: open, 4: closed, 5: flushing, 6: flushcomplete"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_connection_state: IntGaugeVec::new(
+                opts("ibc_connection_state", "IBC connection state of the channel. 0: uninitialized, 1: init, 2: tryopen, 3: open"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id", "connection_id"],
+            )
+            .expect("metric can be created"),
+            ibc_query_status: IntGaugeVec::new(
+                opts("ibc_query_status", "IBC Query Status show the ibc query is successful or not. 0: can access, 1: cannot access"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id", "query_endpoint_url"],
+            )
+            .expect("metric can be created"),
+            ibc_client_status: IntGaugeVec::new(
+                opts("ibc_client_status", "IBC client status. 0: (expiry_time - now) > min_time_before_client_expiration, 1: (expiry_time - now) < min_time_before_client_expiration"),
+                &with_threshold(&["chain_id", "port_id", "channel_id", "destination_chain_id"], "min_time_before_client_expiration"),
+            )
+            .expect("metric can be created"),
+            ibc_client_time_before_expire: IntGaugeVec::new(
+                opts("ibc_client_time_before_expire", "the times left before client reach ibc client expiration in seconds"),
+                &with_threshold(&["chain_id", "port_id", "channel_id", "destination_chain_id"], "min_time_before_client_expiration"),
+            )
+            .expect("metric can be created"),
+            ibc_client_onchain_status: IntGaugeVec::new(
+                opts("ibc_client_onchain_status", "IBC client status as reported by the chain. 0: Active, 1: Expired, 2: Frozen, 3: Unauthorized, 4: Unknown"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id", "client_id"],
+            )
+            .expect("metric can be created"),
+            ibc_query_node_sync_status: IntGaugeVec::new(
+                opts("ibc_query_node_sync_status", "IBC query node sync status. 0: blockheight is moving, 1: blockheight is not synced"),
+                &["chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_query_duration: HistogramVec::new(
+                HistogramOpts {
+                    common_opts: opts("ibc_query_duration_seconds", "duration in seconds of the gRPC queries, per endpoint and method"),
+                    buckets: QUERY_DURATION_BUCKETS.to_vec(),
+                },
+                &["chain_id", "endpoint", "method"],
+            )
+            .expect("metric can be created"),
+            ibc_query_failures: IntCounterVec::new(
+                opts("ibc_query_failures_total", "no of failed gRPC queries, per endpoint, method and gRPC status code"),
+                &["chain_id", "endpoint", "method", "code"],
+            )
+            .expect("metric can be created"),
+            ibc_count_threshold: IntGaugeVec::new(
+                opts("ibc_count_threshold", "min_total of the channel, ibc_status is 1 from this no of ibc packet commitments"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            ibc_client_expiry_threshold: IntGaugeVec::new(
+                opts("ibc_client_expiry_threshold_seconds", "min_time_before_client_expiration of the channel in seconds, ibc_client_status is 1 from this time before expiration"),
+                &["chain_id", "port_id", "channel_id", "destination_chain_id"],
+            )
+            .expect("metric can be created"),
+            registry: Registry::new(),
+            threshold_labels: config.threshold_labels,
+            updated: Mutex::default(),
+        };
+        for collector in metrics.collectors() {
+            metrics
+                .registry
+                .register(collector)
+                .expect("collector can be registered");
+        }
+        metrics
+    }
+
+    /// Every collector of the watcher, in the order they are registered
+    pub fn collectors(&self) -> Vec<Box<dyn Collector>> {
+        vec![
+            Box::new(self.ibc_status.clone()),
+            Box::new(self.ibc_count.clone()),
+            Box::new(self.ibc_unreceived_packets.clone()),
+            Box::new(self.ibc_received_unacknowledged_packets.clone()),
+            Box::new(self.ibc_unrelayed_acks.clone()),
+            Box::new(self.ibc_unrelayed_acks_status.clone()),
+            Box::new(self.ibc_oldest_packet_age.clone()),
+            Box::new(self.ibc_packet_age_status.clone()),
+            Box::new(self.ibc_packets_near_timeout.clone()),
+            Box::new(self.ibc_packets_timed_out.clone()),
+            Box::new(self.ibc_watcher_task_restarts.clone()),
+            Box::new(self.ibc_channel_state.clone()),
+            Box::new(self.ibc_connection_state.clone()),
+            Box::new(self.ibc_query_status.clone()),
+            Box::new(self.ibc_client_status.clone()),
+            Box::new(self.ibc_client_time_before_expire.clone()),
+            Box::new(self.ibc_client_onchain_status.clone()),
+            Box::new(self.ibc_query_node_sync_status.clone()),
+            Box::new(self.ibc_query_duration.clone()),
+            Box::new(self.ibc_query_failures.clone()),
+            Box::new(self.ibc_count_threshold.clone()),
+            Box::new(self.ibc_client_expiry_threshold.clone()),
+        ]
+    }
+
+    /// The gauges, whose series expire when they are not updated
+    fn gauges(&self) -> [&IntGaugeVec; 18] {
+        [
+            &self.ibc_status,
+            &self.ibc_count,
+            &self.ibc_unreceived_packets,
+            &self.ibc_received_unacknowledged_packets,
+            &self.ibc_unrelayed_acks,
+            &self.ibc_unrelayed_acks_status,
+            &self.ibc_oldest_packet_age,
+            &self.ibc_packet_age_status,
+            &self.ibc_packets_near_timeout,
+            &self.ibc_channel_state,
+            &self.ibc_connection_state,
+            &self.ibc_query_status,
+            &self.ibc_client_status,
+            &self.ibc_client_time_before_expire,
+            &self.ibc_client_onchain_status,
+            &self.ibc_query_node_sync_status,
+            &self.ibc_count_threshold,
+            &self.ibc_client_expiry_threshold,
+        ]
+    }
+
+    /// The labels of a channel, followed by the threshold in the legacy
+    /// layout
+    fn with_threshold<'a>(&self, labels: &'a [&'a str; 5]) -> &'a [&'a str] {
+        if self.threshold_labels {
+            labels
+        } else {
+            &labels[..4]
+        }
+    }
+
+    /// Sets the series of the gauge and records when it was updated
+    pub fn set_gauge(&self, gauge: &IntGaugeVec, labels: &[&str], value: i64) {
+        // held while setting so an expiry cannot remove the series in between
+        let mut updated = self.updated.lock().expect("lock is not poisoned");
+        gauge.with_label_values(labels).set(value);
+        updated.insert(
+            (
+                gauge.desc()[0].fq_name.clone(),
+                labels.iter().map(|x| x.to_string()).collect(),
+            ),
+            Instant::now(),
+        );
+    }
+
+    /// Removes the gauge series which were not updated within the ttl and
+    /// returns how many were removed. Counters and histograms are kept.
+    pub fn expire(&self, ttl: Duration) -> usize {
+        match Instant::now().checked_sub(ttl) {
+            Some(cutoff) => self.expire_before(cutoff),
+            None => 0,
+        }
+    }
+
+    fn expire_before(&self, cutoff: Instant) -> usize {
+        let gauges = self.gauges();
+        let mut updated = self.updated.lock().expect("lock is not poisoned");
+        let mut expired = 0;
+        updated.retain(|(name, labels), at| {
+            if *at >= cutoff {
+                return true;
+            }
+            // the series may be gone already, removed with its channel
+            if let Some(gauge) = gauges.iter().find(|x| x.desc()[0].fq_name == *name) {
+                let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+                if gauge.remove_label_values(&labels).is_ok() {
+                    expired += 1;
+                }
+            }
+            false
+        });
+        expired
+    }
+}
+
+/// Creates the metrics from the config, before anything is recorded. The
+/// namespace and constant labels cannot change afterwards.
+pub fn init(config: &PrometheusConfig) {
+    if METRICS.set(Metrics::new(config)).is_err() {
+        error!("metrics are already created, keeping their namespace and constant labels");
+    }
+}
+
+/// The metrics created by `init`, or with the default config when it was not
+/// called
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::new(&PrometheusConfig::default()))
+}
+
+/// A setter for ibc_status, make sure all the labels are set and types are correct
+///
+/// The threshold is only a label with `threshold_labels`.
 pub fn ibc_status_setter(
     chain_id: &str,
     port_id: &str,
     channel_id: &str,
     destination_chain_id: &str,
-    min_total: &str,
+    min_total: Count,
     status: i64,
 ) {
-    IBC_STATUS_COLLECTOR
-        .with_label_values(&[
-            chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            min_total,
-        ])
-        .set(status);
+    let min_total = min_total.to_string();
+    let labels = [
+        chain_id,
+        port_id,
+        channel_id,
+        destination_chain_id,
+        &min_total,
+    ];
+    metrics().set_gauge(
+        &metrics().ibc_status,
+        metrics().with_threshold(&labels),
+        status,
+    );
 }
 
-/// A setter for IBC_COUNT_COLLECTOR, make sure all the labels are set and types are correct
+/// A setter for ibc_count, make sure all the labels are set and types are correct
+///
+/// The threshold is only a label with `threshold_labels`.
 pub fn ibc_count_setter(
     chain_id: &str,
     port_id: &str,
     channel_id: &str,
     destination_chain_id: &str,
-    min_total: &str,
+    min_total: Count,
     count: i64,
 ) {
-    IBC_COUNT_COLLECTOR
-        .with_label_values(&[
-            chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            min_total,
-        ])
-        .set(count);
+    let min_total = min_total.to_string();
+    let labels = [
+        chain_id,
+        port_id,
+        channel_id,
+        destination_chain_id,
+        &min_total,
+    ];
+    metrics().set_gauge(
+        &metrics().ibc_count,
+        metrics().with_threshold(&labels),
+        count,
+    );
 }
 
 #[allow(unused_must_use)]
-/// A remover for IBC_COUNT_COLLECTOR, make sure all the labels are set and types are correct
+/// A remover for ibc_count, make sure all the labels are set and types are correct
 pub fn ibc_count_remover(
     chain_id: &str,
     port_id: &str,
     channel_id: &str,
     destination_chain_id: &str,
-    min_total: &str,
+    min_total: Count,
+) {
+    let min_total = min_total.to_string();
+    let labels = [
+        chain_id,
+        port_id,
+        channel_id,
+        destination_chain_id,
+        &min_total,
+    ];
+    metrics()
+        .ibc_count
+        .remove_label_values(metrics().with_threshold(&labels));
+}
+
+/// A setter for ibc_unreceived_packets and ibc_received_unacknowledged_packets, make sure all the labels are set and types are correct
+pub fn ibc_unreceived_packets_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    unreceived: i64,
+    received_unacknowledged: i64,
+) {
+    let labels = [chain_id, port_id, channel_id, destination_chain_id];
+    metrics().set_gauge(&metrics().ibc_unreceived_packets, &labels, unreceived);
+    metrics().set_gauge(
+        &metrics().ibc_received_unacknowledged_packets,
+        &labels,
+        received_unacknowledged,
+    );
+}
+
+#[allow(unused_must_use)]
+/// A remover for ibc_unreceived_packets and ibc_received_unacknowledged_packets, make sure all the labels are set and types are correct
+pub fn ibc_unreceived_packets_remover(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
 ) {
-    IBC_COUNT_COLLECTOR.remove_label_values(&[
+    let labels = [chain_id, port_id, channel_id, destination_chain_id];
+    metrics()
+        .ibc_unreceived_packets
+        .remove_label_values(&labels);
+    metrics()
+        .ibc_received_unacknowledged_packets
+        .remove_label_values(&labels);
+}
+
+/// A setter for ibc_unrelayed_acks and ibc_unrelayed_acks_status, make sure all the labels are set and types are correct
+pub fn ibc_unrelayed_acks_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    count: i64,
+    status: i64,
+) {
+    let labels = [chain_id, port_id, channel_id, destination_chain_id];
+    metrics().set_gauge(&metrics().ibc_unrelayed_acks, &labels, count);
+    metrics().set_gauge(&metrics().ibc_unrelayed_acks_status, &labels, status);
+}
+
+#[allow(unused_must_use)]
+/// A remover for ibc_unrelayed_acks and ibc_unrelayed_acks_status, make sure all the labels are set and types are correct
+pub fn ibc_unrelayed_acks_remover(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+) {
+    let labels = [chain_id, port_id, channel_id, destination_chain_id];
+    metrics().ibc_unrelayed_acks.remove_label_values(&labels);
+    metrics()
+        .ibc_unrelayed_acks_status
+        .remove_label_values(&labels);
+}
+
+/// A setter for ibc_oldest_packet_age and, when max_packet_age is configured, ibc_packet_age_status, make sure all the labels are set and types are correct
+pub fn ibc_oldest_packet_age_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    age: i64,
+    status: Option<i64>,
+) {
+    let labels = [chain_id, port_id, channel_id, destination_chain_id];
+    metrics().set_gauge(&metrics().ibc_oldest_packet_age, &labels, age);
+    if let Some(status) = status {
+        metrics().set_gauge(&metrics().ibc_packet_age_status, &labels, status);
+    }
+}
+
+#[allow(unused_must_use)]
+/// A remover for ibc_oldest_packet_age and ibc_packet_age_status, make sure all the labels are set and types are correct
+pub fn ibc_oldest_packet_age_remover(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+) {
+    let labels = [chain_id, port_id, channel_id, destination_chain_id];
+    metrics().ibc_oldest_packet_age.remove_label_values(&labels);
+    metrics().ibc_packet_age_status.remove_label_values(&labels);
+}
+
+/// A setter for ibc_packets_near_timeout, make sure all the labels are set and types are correct
+pub fn ibc_packets_near_timeout_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    count: i64,
+) {
+    metrics().set_gauge(
+        &metrics().ibc_packets_near_timeout,
+        &[chain_id, port_id, channel_id, destination_chain_id],
+        count,
+    );
+}
+
+#[allow(unused_must_use)]
+/// A remover for ibc_packets_near_timeout, make sure all the labels are set and types are correct
+pub fn ibc_packets_near_timeout_remover(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+) {
+    metrics().ibc_packets_near_timeout.remove_label_values(&[
         chain_id,
         port_id,
         channel_id,
         destination_chain_id,
-        min_total,
     ]);
 }
 
-/// A setter for IBC_QUERY_STATUS_COLLECTOR, make sure all the labels are set and types are correct
+/// An incrementer for ibc_packets_timed_out, make sure all the labels are set and types are correct
+pub fn ibc_packets_timed_out_incrementer(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    count: u64,
+) {
+    metrics()
+        .ibc_packets_timed_out
+        .with_label_values(&[chain_id, port_id, channel_id, destination_chain_id])
+        .inc_by(count);
+}
+
+/// An incrementer for ibc_watcher_task_restarts, make sure all the labels are set and types are correct
+pub fn ibc_watcher_task_restarts_incrementer(
+    task: &str,
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+) {
+    metrics()
+        .ibc_watcher_task_restarts
+        .with_label_values(&[task, chain_id, port_id, channel_id])
+        .inc();
+}
+
+/// A setter for ibc_channel_state, make sure all the labels are set and types are correct
+pub fn ibc_channel_state_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    state: i64,
+) {
+    metrics().set_gauge(
+        &metrics().ibc_channel_state,
+        &[chain_id, port_id, channel_id, destination_chain_id],
+        state,
+    );
+}
+
+/// A setter for ibc_connection_state, make sure all the labels are set and types are correct
+pub fn ibc_connection_state_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    connection_id: &str,
+    state: i64,
+) {
+    metrics().set_gauge(
+        &metrics().ibc_connection_state,
+        &[
+            chain_id,
+            port_id,
+            channel_id,
+            destination_chain_id,
+            connection_id,
+        ],
+        state,
+    );
+}
+
+/// A setter for ibc_query_status, make sure all the labels are set and types are correct
+///
+/// Only the endpoint currently serving the channel is exported, the series of
+/// any previously active endpoint is removed.
 pub fn ibc_query_status_setter(
     chain_id: &str,
     port_id: &str,
@@ -106,18 +611,30 @@ pub fn ibc_query_status_setter(
     query_endpoint_url: &str,
     status: i64,
 ) {
-    IBC_QUERY_STATUS_COLLECTOR
-        .with_label_values(&[
+    remove_series(&metrics().ibc_query_status, |labels| {
+        labels["chain_id"] == chain_id
+            && labels["port_id"] == port_id
+            && labels["channel_id"] == channel_id
+            && labels["destination_chain_id"] == destination_chain_id
+            && labels["query_endpoint_url"] != query_endpoint_url
+    });
+
+    metrics().set_gauge(
+        &metrics().ibc_query_status,
+        &[
             chain_id,
             port_id,
             channel_id,
             destination_chain_id,
             query_endpoint_url,
-        ])
-        .set(status);
+        ],
+        status,
+    );
 }
 
-/// A setter for IBC_CLIENT_STATUS_COLLECTOR, make sure all the labels are set and types are correct
+/// A setter for ibc_client_status, make sure all the labels are set and types are correct
+///
+/// The threshold is only a label with `threshold_labels`.
 pub fn ibc_client_status_setter(
     chain_id: &str,
     port_id: &str,
@@ -126,18 +643,23 @@ pub fn ibc_client_status_setter(
     min_time_before_client_expiration: &str,
     status: i64,
 ) {
-    IBC_CLIENT_STATUS_COLLECTOR
-        .with_label_values(&[
-            chain_id,
-            port_id,
-            channel_id,
-            destination_chain_id,
-            min_time_before_client_expiration,
-        ])
-        .set(status);
+    let labels = [
+        chain_id,
+        port_id,
+        channel_id,
+        destination_chain_id,
+        min_time_before_client_expiration,
+    ];
+    metrics().set_gauge(
+        &metrics().ibc_client_status,
+        metrics().with_threshold(&labels),
+        status,
+    );
 }
 
-/// A setter for IBC_CLIENT_TIME_BEFORE_EXPIRE_COLLECTOR, make sure all the labels are set and types are correct
+/// A setter for ibc_client_time_before_expire, make sure all the labels are set and types are correct
+///
+/// The threshold is only a label with `threshold_labels`.
 pub fn ibc_client_time_before_expire_setter(
     chain_id: &str,
     port_id: &str,
@@ -146,43 +668,214 @@ pub fn ibc_client_time_before_expire_setter(
     min_time_before_client_expiration: &str,
     time_before_expire: i64,
 ) {
-    IBC_CLIENT_TIME_BEFORE_EXPIRE_COLLECTOR
-        .with_label_values(&[
+    let labels = [
+        chain_id,
+        port_id,
+        channel_id,
+        destination_chain_id,
+        min_time_before_client_expiration,
+    ];
+    metrics().set_gauge(
+        &metrics().ibc_client_time_before_expire,
+        metrics().with_threshold(&labels),
+        time_before_expire,
+    );
+}
+
+/// A setter for ibc_count_threshold, make sure all the labels are set and types are correct
+pub fn ibc_count_threshold_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    min_total: Count,
+) {
+    metrics().set_gauge(
+        &metrics().ibc_count_threshold,
+        &[chain_id, port_id, channel_id, destination_chain_id],
+        min_total.as_i64(),
+    );
+}
+
+/// A setter for ibc_client_expiry_threshold_seconds, make sure all the labels are set and types are correct
+pub fn ibc_client_expiry_threshold_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    min_time_before_client_expiration: Duration,
+) {
+    metrics().set_gauge(
+        &metrics().ibc_client_expiry_threshold,
+        &[chain_id, port_id, channel_id, destination_chain_id],
+        min_time_before_client_expiration.as_secs() as i64,
+    );
+}
+
+/// A setter for ibc_client_onchain_status, make sure all the labels are set and types are correct
+pub fn ibc_client_onchain_status_setter(
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+    destination_chain_id: &str,
+    client_id: &str,
+    status: i64,
+) {
+    metrics().set_gauge(
+        &metrics().ibc_client_onchain_status,
+        &[
             chain_id,
             port_id,
             channel_id,
             destination_chain_id,
-            min_time_before_client_expiration,
-        ])
-        .set(time_before_expire);
+            client_id,
+        ],
+        status,
+    );
 }
 
-/// A setter for IBC_QUERY_NODE_SYNC_STATUS_COLLECTOR, make sure all the labels are set and types are correct
+/// A setter for ibc_query_node_sync_status, make sure all the labels are set and types are correct
 pub fn ibc_query_node_sync_status_setter(chain_id: &str, status: i64) {
-    IBC_QUERY_NODE_SYNC_STATUS_COLLECTOR
-        .with_label_values(&[chain_id])
-        .set(status);
-}
-
-pub fn register_custom_metrics() {
-    REGISTRY
-        .register(Box::new(IBC_STATUS_COLLECTOR.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(IBC_COUNT_COLLECTOR.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(IBC_QUERY_STATUS_COLLECTOR.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(IBC_CLIENT_STATUS_COLLECTOR.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(IBC_CLIENT_TIME_BEFORE_EXPIRE_COLLECTOR.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(IBC_QUERY_NODE_SYNC_STATUS_COLLECTOR.clone()))
-        .expect("collector can be registered");
+    metrics().set_gauge(&metrics().ibc_query_node_sync_status, &[chain_id], status);
+}
+
+/// An observer for ibc_query_duration_seconds, make sure all the labels are set and types are correct
+pub fn ibc_query_duration_observer(
+    chain_id: &str,
+    endpoint: &str,
+    method: &str,
+    duration: Duration,
+) {
+    metrics()
+        .ibc_query_duration
+        .with_label_values(&[chain_id, endpoint, method])
+        .observe(duration.as_secs_f64());
+}
+
+/// An incrementer for ibc_query_failures_total, make sure all the labels are set and types are correct
+pub fn ibc_query_failures_incrementer(chain_id: &str, endpoint: &str, method: &str, code: Code) {
+    metrics()
+        .ibc_query_failures
+        .with_label_values(&[chain_id, endpoint, method, &format!("{:?}", code)])
+        .inc();
+}
+
+/// Removes every series of the collector whose labels satisfy the predicate
+fn remove_series<T: MetricVecBuilder>(
+    collector: &MetricVec<T>,
+    predicate: impl Fn(&HashMap<&str, &str>) -> bool,
+) {
+    let variable_labels: Vec<String> = collector
+        .desc()
+        .iter()
+        .flat_map(|desc| desc.variable_labels.clone())
+        .collect();
+    for family in collector.collect() {
+        for metric in family.get_metric() {
+            // without the constant labels, remove only takes the variable ones
+            let labels: HashMap<&str, &str> = metric
+                .get_label()
+                .iter()
+                .filter(|pair| variable_labels.iter().any(|x| x == pair.get_name()))
+                .map(|pair| (pair.get_name(), pair.get_value()))
+                .collect();
+            if predicate(&labels) {
+                let _ = collector.remove(&labels);
+            }
+        }
+    }
+}
+
+/// The gauges labelled by channel
+fn channel_gauges() -> [&'static IntGaugeVec; 17] {
+    [
+        &metrics().ibc_status,
+        &metrics().ibc_count,
+        &metrics().ibc_unreceived_packets,
+        &metrics().ibc_received_unacknowledged_packets,
+        &metrics().ibc_unrelayed_acks,
+        &metrics().ibc_unrelayed_acks_status,
+        &metrics().ibc_oldest_packet_age,
+        &metrics().ibc_packet_age_status,
+        &metrics().ibc_packets_near_timeout,
+        &metrics().ibc_channel_state,
+        &metrics().ibc_connection_state,
+        &metrics().ibc_query_status,
+        &metrics().ibc_client_status,
+        &metrics().ibc_client_time_before_expire,
+        &metrics().ibc_client_onchain_status,
+        &metrics().ibc_count_threshold,
+        &metrics().ibc_client_expiry_threshold,
+    ]
+}
+
+fn is_channel(
+    labels: &HashMap<&str, &str>,
+    chain_id: &str,
+    port_id: &str,
+    channel_id: &str,
+) -> bool {
+    labels["chain_id"] == chain_id
+        && labels["port_id"] == port_id
+        && labels["channel_id"] == channel_id
+}
+
+/// A remover for ibc_status and ibc_count, removes the series of the channel whatever its min_total
+pub fn ibc_status_metrics_remover(chain_id: &str, port_id: &str, channel_id: &str) {
+    for collector in [&metrics().ibc_status, &metrics().ibc_count] {
+        remove_series(collector, |labels| {
+            is_channel(labels, chain_id, port_id, channel_id)
+        });
+    }
+}
+
+/// A remover for ibc_client_status and ibc_client_time_before_expire, removes the series of the channel whatever its min_time_before_client_expiration
+pub fn ibc_client_metrics_remover(chain_id: &str, port_id: &str, channel_id: &str) {
+    for collector in [
+        &metrics().ibc_client_status,
+        &metrics().ibc_client_time_before_expire,
+    ] {
+        remove_series(collector, |labels| {
+            is_channel(labels, chain_id, port_id, channel_id)
+        });
+    }
+}
+
+/// A remover for all series of a channel
+pub fn ibc_channel_metrics_remover(chain_id: &str, port_id: &str, channel_id: &str) {
+    for collector in channel_gauges() {
+        remove_series(collector, |labels| {
+            is_channel(labels, chain_id, port_id, channel_id)
+        });
+    }
+    remove_series(&metrics().ibc_packets_timed_out, |labels| {
+        is_channel(labels, chain_id, port_id, channel_id)
+    });
+    remove_series(&metrics().ibc_watcher_task_restarts, |labels| {
+        is_channel(labels, chain_id, port_id, channel_id)
+    });
+}
+
+/// A remover for all series of a chain, including its discovered channels
+pub fn ibc_chain_metrics_remover(chain_id: &str) {
+    for collector in channel_gauges() {
+        remove_series(collector, |labels| labels["chain_id"] == chain_id);
+    }
+    remove_series(&metrics().ibc_query_node_sync_status, |labels| {
+        labels["chain_id"] == chain_id
+    });
+    remove_series(&metrics().ibc_packets_timed_out, |labels| {
+        labels["chain_id"] == chain_id
+    });
+    remove_series(&metrics().ibc_watcher_task_restarts, |labels| {
+        labels["chain_id"] == chain_id
+    });
+    remove_series(&metrics().ibc_query_duration, |labels| {
+        labels["chain_id"] == chain_id
+    });
+    remove_series(&metrics().ibc_query_failures, |labels| {
+        labels["chain_id"] == chain_id
+    });
 }
 
 pub async fn metrics_handler() -> Result<impl Reply, Rejection> {
@@ -190,7 +883,7 @@ pub async fn metrics_handler() -> Result<impl Reply, Rejection> {
     let encoder = prometheus::TextEncoder::new();
 
     let mut buffer = Vec::new();
-    if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
+    if let Err(e) = encoder.encode(&metrics().registry.gather(), &mut buffer) {
         error!("could not encode custom metrics: {:?}", e);
     };
     let mut res = String::from_utf8(buffer.clone()).unwrap_or_else(|e| {
@@ -212,3 +905,140 @@ pub async fn metrics_handler() -> Result<impl Reply, Rejection> {
     res.push_str(&res_custom);
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variable_labels_are_listed() {
+        let labels = |threshold_labels| {
+            let mut labels: Vec<String> = Metrics::new(&PrometheusConfig {
+                threshold_labels,
+                ..Default::default()
+            })
+            .collectors()
+            .iter()
+            .flat_map(|x| x.desc())
+            .flat_map(|desc| desc.variable_labels.clone())
+            .collect();
+            labels.sort();
+            labels.dedup();
+            labels
+        };
+        let mut listed: Vec<String> = VARIABLE_LABELS.iter().map(|x| x.to_string()).collect();
+        listed.sort();
+        assert_eq!(labels(false), listed);
+        listed.extend(THRESHOLD_LABELS.iter().map(|x| x.to_string()));
+        listed.sort();
+        assert_eq!(labels(true), listed);
+    }
+
+    #[test]
+    fn namespace_and_const_labels() {
+        let metrics = Metrics::new(&PrometheusConfig {
+            namespace: Some("mantra".to_string()),
+            const_labels: [("environment".to_string(), "testnet".to_string())].into(),
+            ..Default::default()
+        });
+        metrics
+            .ibc_count
+            .with_label_values(&["chain_A", "transfer", "channel-0", "chain_B"])
+            .set(3);
+
+        let families = metrics.registry.gather();
+        let family = families
+            .iter()
+            .find(|x| x.get_name() == "mantra_ibc_count")
+            .expect("metric is namespaced");
+        let labels = family.get_metric()[0].get_label();
+        assert!(labels
+            .iter()
+            .any(|x| x.get_name() == "environment" && x.get_value() == "testnet"));
+
+        remove_series(&metrics.ibc_count, |labels| labels["chain_id"] == "chain_A");
+        assert!(metrics.ibc_count.collect()[0].get_metric().is_empty());
+    }
+
+    #[test]
+    fn series_expire_when_not_updated() {
+        let metrics = Metrics::new(&PrometheusConfig::default());
+        let stale = ["chain_A", "transfer", "channel-0", "chain_B"];
+        let fresh = ["chain_A", "transfer", "channel-1", "chain_C"];
+        metrics.set_gauge(&metrics.ibc_client_status, &stale, 1);
+        metrics.set_gauge(&metrics.ibc_query_node_sync_status, &["chain_A"], 0);
+        std::thread::sleep(Duration::from_millis(5));
+        let cutoff = Instant::now();
+        std::thread::sleep(Duration::from_millis(5));
+        metrics.set_gauge(&metrics.ibc_client_status, &fresh, 0);
+        // counters are left alone
+        metrics
+            .ibc_packets_timed_out
+            .with_label_values(&["chain_A", "transfer", "channel-0", "chain_B"])
+            .inc();
+
+        assert_eq!(metrics.expire_before(cutoff), 2);
+        let series = metrics.ibc_client_status.collect()[0].get_metric().to_vec();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].get_label()[1].get_value(), "channel-1");
+        assert!(metrics.ibc_query_node_sync_status.collect()[0]
+            .get_metric()
+            .is_empty());
+        assert_eq!(
+            metrics.ibc_packets_timed_out.collect()[0]
+                .get_metric()
+                .len(),
+            1
+        );
+
+        // updating an expired series brings it back
+        metrics.set_gauge(&metrics.ibc_client_status, &stale, 1);
+        assert_eq!(metrics.expire_before(cutoff), 0);
+        assert_eq!(metrics.ibc_client_status.collect()[0].get_metric().len(), 2);
+    }
+
+    #[test]
+    fn thresholds_are_labels_only_in_legacy_layout() {
+        let labels = ["chain_A", "transfer", "channel-0", "chain_B", "10"];
+        let variable_labels =
+            |metrics: &Metrics| metrics.ibc_count.desc()[0].variable_labels.clone();
+
+        let metrics = Metrics::new(&PrometheusConfig::default());
+        assert_eq!(
+            variable_labels(&metrics),
+            ["chain_id", "port_id", "channel_id", "destination_chain_id"]
+        );
+        metrics.set_gauge(&metrics.ibc_count, metrics.with_threshold(&labels), 3);
+        metrics.set_gauge(&metrics.ibc_count_threshold, &labels[..4], 10);
+
+        let legacy = Metrics::new(&PrometheusConfig {
+            threshold_labels: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            variable_labels(&legacy),
+            [
+                "chain_id",
+                "port_id",
+                "channel_id",
+                "destination_chain_id",
+                "min_total"
+            ]
+        );
+        legacy.set_gauge(&legacy.ibc_count, legacy.with_threshold(&labels), 3);
+        assert_eq!(
+            legacy.ibc_client_time_before_expire.desc()[0]
+                .variable_labels
+                .last()
+                .map(String::as_str),
+            Some("min_time_before_client_expiration")
+        );
+        // the threshold gauges are exported in both layouts
+        legacy.set_gauge(&legacy.ibc_count_threshold, &labels[..4], 10);
+        assert!(legacy
+            .registry
+            .gather()
+            .iter()
+            .any(|x| x.get_name() == "ibc_count_threshold"));
+    }
+}